@@ -1,12 +1,21 @@
 use crate::{
-    error,
+    collections,
+    error::{self, ServerError},
+    ingest::{self, ChunkMeta, DocChunk},
+    registry,
+    retrieval::RagStrategy,
     utils::{print_log_begin_separator, print_log_end_separator},
-    GLOBAL_RAG_PROMPT, SERVER_INFO,
+    vector_store::{self, RetrievalMode, RetrieveFilter},
+    QdrantConfig, DOWNLOAD_ALLOWED_HOSTS, GLOBAL_RAG_PROMPT, SERVER_INFO,
+};
+use chat_prompts::{
+    chat::{BuildChatPrompt, ChatPrompt},
+    error as ChatPromptsError, MergeRagContext, MergeRagContextPolicy,
 };
-use chat_prompts::{error as ChatPromptsError, MergeRagContext, MergeRagContextPolicy};
 use endpoints::{
     chat::{ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionUserMessageContent},
-    embeddings::EmbeddingRequest,
+    common::Usage,
+    embeddings::{EmbeddingRequest, EmbeddingsResponse},
     files::FileObject,
     rag::{ChunksRequest, ChunksResponse, RagEmbeddingRequest},
 };
@@ -14,7 +23,10 @@ use futures_util::TryStreamExt;
 use hyper::{body::to_bytes, Body, Method, Request, Response};
 use multipart::server::{Multipart, ReadEntry, ReadEntryResult};
 use multipart_2021 as multipart;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::{
+    collections::{BTreeMap, HashMap},
     fs::{self, File},
     io::{Cursor, Read, Write},
     path::Path,
@@ -51,12 +63,21 @@ pub(crate) async fn models_handler() -> Result<Response<Body>, hyper::Error> {
 }
 
 /// Process a chat-completion request in stream mode and returns a chat-completion response with the answer from the model.
+///
+/// The `extra` fields are added to the first chunk of the stream.
 async fn chat_completions_stream(
     mut chat_request: ChatCompletionRequest,
+    extra: Map<String, Value>,
 ) -> Result<Response<Body>, hyper::Error> {
     match llama_core::chat::chat_completions_stream(&mut chat_request).await {
         Ok(stream) => {
-            let stream = stream.map_err(|e| e.to_string());
+            let mut extra = Some(extra).filter(|extra| !extra.is_empty());
+            let stream = stream
+                .map_ok(move |chunk| match extra.take() {
+                    Some(extra) => add_fields_to_chunk(chunk, extra),
+                    None => chunk,
+                })
+                .map_err(|e| e.to_string());
 
             let result = Response::builder()
                 .header("Access-Control-Allow-Origin", "*")
@@ -76,12 +97,44 @@ async fn chat_completions_stream(
     }
 }
 
+/// Add the fields to a `data: {...}` chunk of a chat completion stream. Other chunks are left as is.
+fn add_fields_to_chunk(chunk: String, fields: Map<String, Value>) -> String {
+    let json = match chunk.strip_prefix("data: ") {
+        Some(json) => json.trim_end(),
+        None => return chunk,
+    };
+
+    match serde_json::from_str::<Value>(json) {
+        Ok(Value::Object(mut object)) => {
+            object.extend(fields);
+            format!("data: {}\n\n", Value::Object(object))
+        }
+        _ => chunk,
+    }
+}
+
 /// Process a chat-completion request and returns a chat-completion response with the answer from the model.
+///
+/// The `extra` fields are added to the chat completion object.
 async fn chat_completions(
     mut chat_request: ChatCompletionRequest,
+    extra: Map<String, Value>,
 ) -> Result<Response<Body>, hyper::Error> {
     match llama_core::chat::chat_completions(&mut chat_request).await {
         Ok(chat_completion_object) => {
+            let mut chat_completion_object = match serde_json::to_value(chat_completion_object) {
+                Ok(chat_completion_object) => chat_completion_object,
+                Err(e) => {
+                    return error::internal_server_error(format!(
+                        "Fail to serialize chat completion object. {}",
+                        e
+                    ));
+                }
+            };
+            if let Value::Object(object) = &mut chat_completion_object {
+                object.extend(extra);
+            }
+
             // serialize chat completion object
             let s = match serde_json::to_string(&chat_completion_object) {
                 Ok(s) => s,
@@ -152,12 +205,10 @@ pub(crate) async fn _rag_doc_chunks_to_embeddings_handler(
 
 /// Compute embeddings for document chunks and persist them in the specified Qdrant server.
 ///
-/// Note tht the body of the request is deserialized to a `EmbeddingRequest` instance.
+/// Note tht the body of the request is deserialized to a `EmbeddingRequest` instance. The optional `file_id` and `filename` fields name the archived document the chunks come from.
 pub(crate) async fn rag_doc_chunks_to_embeddings2_handler(
     mut req: Request<Body>,
 ) -> Result<Response<Body>, hyper::Error> {
-    print_log_begin_separator("RAG (Embeddings for chunks)", Some("*"), None);
-
     // parse request
     let body_bytes = to_bytes(req.body_mut()).await?;
     let embedding_request: EmbeddingRequest = match serde_json::from_slice(&body_bytes) {
@@ -166,28 +217,69 @@ pub(crate) async fn rag_doc_chunks_to_embeddings2_handler(
             return error::bad_request(format!("Fail to parse embedding request: {msg}", msg = e));
         }
     };
-
-    let server_info = match SERVER_INFO.get() {
-        Some(server_info) => server_info,
-        None => {
-            return error::internal_server_error("The server info is not set.");
-        }
+    let source: EmbeddingSource = serde_json::from_slice(&body_bytes).unwrap_or_default();
+    let collection_name = match target_collection(source.collection.as_deref()) {
+        Ok(collection_name) => collection_name,
+        Err(e) => return error::bad_request(e.to_string()),
     };
 
-    // create rag embedding request
-    let rag_embedding_request = RagEmbeddingRequest::from_embedding_request(
-        embedding_request,
-        server_info.qdrant_config.url.clone(),
-        server_info.qdrant_config.collection_name.clone(),
-    );
+    // the metadata of the archived document goes along with every chunk
+    let document =
+        match (&source.file_id, &source.filename) {
+            (Some(id), Some(filename)) => {
+                let record = match registry::list(Some(id)) {
+                    Ok(records) => records
+                        .into_iter()
+                        .find(|record| &record.filename == filename),
+                    Err(e) => return error::internal_server_error(e.to_string()),
+                };
 
-    let embedding_response =
-        match llama_core::rag::rag_doc_chunks_to_embeddings(&rag_embedding_request).await {
-            Ok(embedding_response) => embedding_response,
-            Err(e) => return error::internal_server_error(e.to_string()),
+                match record {
+                    Some(record) => document_meta(id, filename, record.created_at, &record.tags),
+                    None => {
+                        return error::not_found(format!(
+                            "Not found file: {} in archive id: {}",
+                            filename, id
+                        ))
+                    }
+                }
+            }
+            (None, None) => ChunkMeta::default(),
+            _ => return error::bad_request(
+                "Both `file_id` and `filename` are required to name the document of the chunks.",
+            ),
         };
 
-    print_log_begin_separator("RAG (Embeddings for chunks)", Some("*"), None);
+    let mut chunks = embedding_request
+        .input
+        .into_iter()
+        .enumerate()
+        .map(|(idx, text)| DocChunk {
+            text,
+            meta: ChunkMeta {
+                chunk_index: Some(idx),
+                ..Default::default()
+            },
+        })
+        .collect::<Vec<DocChunk>>();
+    ingest::set_document(&mut chunks, &document);
+
+    let embedding_response = match embed_chunks(&chunks, &collection_name).await {
+        Ok(embedding_response) => embedding_response,
+        Err(e) => return error::internal_server_error(e.to_string()),
+    };
+
+    if let (Some(id), Some(filename)) = (&source.file_id, &source.filename) {
+        if let Err(e) = registry::set_embedded(
+            id,
+            filename,
+            chunks.len(),
+            &embedding_response.model,
+            &collection_name,
+        ) {
+            return error::internal_server_error(e.to_string());
+        }
+    }
 
     // serialize embedding object
     match serde_json::to_string(&embedding_response) {
@@ -209,6 +301,20 @@ pub(crate) async fn rag_doc_chunks_to_embeddings2_handler(
     }
 }
 
+/// Fields of `/v1/embeddings` requests besides those of `EmbeddingRequest`.
+#[derive(Debug, Default, Deserialize)]
+struct EmbeddingSource {
+    /// Id of the archived file the chunks come from
+    #[serde(default)]
+    file_id: Option<String>,
+    /// Name of the archived file the chunks come from
+    #[serde(default)]
+    filename: Option<String>,
+    /// Collection to persist the chunks in. Defaults to the default collection.
+    #[serde(default)]
+    collection: Option<String>,
+}
+
 /// Query a user input and return a chat-completion response with the answer from the model.
 ///
 /// Note that the body of the request is deserialized to a `ChatCompletionRequest` instance.
@@ -244,6 +350,16 @@ pub(crate) async fn rag_query_handler(
         }
     };
 
+    let retrieve_options: RetrieveOptions = match serde_json::from_slice(&body_bytes) {
+        Ok(retrieve_options) => retrieve_options,
+        Err(e) => {
+            return error::bad_request(format!(
+                "Fail to parse the retrieval options: {msg}",
+                msg = e
+            ));
+        }
+    };
+
     let server_info = match SERVER_INFO.get() {
         Some(server_info) => server_info,
         None => {
@@ -251,76 +367,41 @@ pub(crate) async fn rag_query_handler(
         }
     };
 
-    println!("\n[+] Computing embeddings for user query ...");
-
-    // * compute embeddings for user query
-    let embedding_response = match chat_request.messages.is_empty() {
-        true => return error::bad_request("Messages should not be empty"),
-        false => {
-            let last_message = chat_request.messages.last().unwrap();
-            match last_message {
-                ChatCompletionRequestMessage::User(user_message) => {
-                    let query_text = match user_message.content() {
-                        ChatCompletionUserMessageContent::Text(text) => text,
-                        _ => {
-                            return error::bad_request(
-                                "The last message must be a text content user message",
-                            )
-                        }
-                    };
-
-                    println!("    * user query: {}\n", query_text);
-
-                    // get the available embedding models
-                    let embedding_model_names = match llama_core::utils::embedding_model_names() {
-                        Ok(model_names) => model_names,
-                        Err(e) => return error::internal_server_error(e.to_string()),
-                    };
-
-                    // create a embedding request
-                    let embedding_request = EmbeddingRequest {
-                        model: embedding_model_names[0].clone(),
-                        input: vec![query_text.clone()],
-                        encoding_format: None,
-                        user: chat_request.user.clone(),
-                    };
-
-                    if let Ok(request_str) = serde_json::to_string_pretty(&embedding_request) {
-                        println!("    * embedding request (json):\n\n{}", request_str);
-                    }
-
-                    let rag_embedding_request = RagEmbeddingRequest {
-                        embedding_request,
-                        qdrant_url: server_info.qdrant_config.url.clone(),
-                        qdrant_collection_name: server_info.qdrant_config.collection_name.clone(),
-                    };
+    // the retrieval settings of the server, overridden by the request
+    let retrieval =
+        match retrieve_options.resolve(&server_info.qdrant_config, chat_request.model.as_deref()) {
+            Ok(retrieval) => retrieval,
+            Err(e) => return error::bad_request(e.to_string()),
+        };
 
-                    // compute embeddings for query
-                    match llama_core::rag::rag_query_to_embeddings(&rag_embedding_request).await {
-                        Ok(embedding_response) => embedding_response,
-                        Err(e) => {
-                            return error::internal_server_error(e.to_string());
-                        }
-                    }
-                }
-                _ => return error::bad_request("The last message must be a user message"),
+    // a model name routed to a collection is answered by the default chat model, unless it names a chat model
+    if retrieval.by_model {
+        let chat_model_names = llama_core::utils::chat_model_names().unwrap_or_default();
+        if let Some(model) = &chat_request.model {
+            if !chat_model_names.contains(model) {
+                println!(
+                    "    * model {} is routed to {}",
+                    model, retrieval.collection_name
+                );
+                chat_request.model = None;
             }
         }
-    };
-    let query_embedding: Vec<f32> = match embedding_response.data.first() {
-        Some(embedding) => embedding.embedding.iter().map(|x| *x as f32).collect(),
-        None => return error::internal_server_error("No embeddings returned"),
+    }
+
+    let query_text = match last_user_query(&chat_request.messages) {
+        Ok(query_text) => query_text,
+        Err(e) => return error::bad_request(e.to_string()),
     };
 
-    println!("\n[+] Retrieving context ...");
+    println!("    * user query: {}", query_text);
 
     // * retrieve context
-    let ro = match llama_core::rag::rag_retrieve_context(
-        query_embedding.as_slice(),
-        server_info.qdrant_config.url.to_string().as_str(),
-        server_info.qdrant_config.collection_name.as_str(),
-        server_info.qdrant_config.limit as usize,
-        Some(server_info.qdrant_config.score_threshold),
+    let ro = match retrieve_context(
+        &query_text,
+        &chat_request.messages,
+        chat_request.user.clone(),
+        &retrieval,
+        chat_request.model.as_deref(),
     )
     .await
     {
@@ -330,67 +411,113 @@ pub(crate) async fn rag_query_handler(
         }
     };
 
-    match ro.points {
-        Some(scored_points) => {
-            match scored_points.is_empty() {
-                true => {
-                    println!(
-                        "    * No point retrieved (score < threshold {})",
-                        server_info.qdrant_config.score_threshold
-                    );
-                    println!("\n[+] Answer the user query ...");
+    // * fit the context in the context window, along with the conversation and the answer
+    let mut context_report = None;
+    let scored_points = match ro.points {
+        Some(scored_points) if !scored_points.is_empty() => {
+            let prompt = match prompt_without_context(
+                &chat_request.messages,
+                chat_request.model.as_deref(),
+                server_info.rag_config.policy,
+            ) {
+                Ok(prompt) => prompt,
+                Err(e) => return error::internal_server_error(e.to_string()),
+            };
+
+            let chat_model = &server_info.rag_config.chat_model;
+            match crate::retrieval::fit_context(
+                scored_points,
+                &prompt,
+                chat_model.ctx_size,
+                chat_request.max_tokens.unwrap_or(chat_model.n_predict),
+                server_info.rag_config.context_token_margin,
+            ) {
+                Ok((scored_points, report)) => {
+                    context_report = Some(report);
+                    scored_points
                 }
-                false => {
-                    // update messages with retrieved context
-                    let mut context = String::new();
-                    for (idx, point) in scored_points.iter().enumerate() {
-                        println!("    * Point {}: score: {}", idx, point.score);
-                        println!("      Source: {}", &point.source);
-
-                        context.push_str(&point.source);
-                        context.push_str("\n\n");
-                    }
+                Err(e) => return error::internal_server_error(e.to_string()),
+            }
+        }
+        _ => {
+            println!(
+                "    * No point retrieved (score < threshold {})",
+                retrieval.score_threshold
+            );
+            vec![]
+        }
+    };
 
-                    if chat_request.messages.is_empty() {
-                        return error::internal_server_error("No message in the chat request.");
+    let mut citations = None;
+    match scored_points.is_empty() {
+        true => println!("\n[+] Answer the user query ..."),
+        false => {
+            // update messages with retrieved context
+            for (idx, point) in scored_points.iter().enumerate() {
+                println!("    * Point {}: score: {}", idx + 1, point.score);
+                if let Some(filename) = &point.meta.filename {
+                    match point.meta.page {
+                        Some(page) => println!("      File: {} (page {})", filename, page),
+                        None => println!("      File: {}", filename),
                     }
+                }
+                if let (Some(symbol), Some(start), Some(end)) = (
+                    &point.meta.symbol,
+                    point.meta.start_line,
+                    point.meta.end_line,
+                ) {
+                    println!("      Symbol: {} (lines {}-{})", symbol, start, end);
+                }
+                println!("      Source: {}", &point.source);
+            }
 
-                    let prompt_template = match llama_core::utils::chat_prompt_template(
-                        chat_request.model.as_deref(),
-                    ) {
-                        Ok(prompt_template) => prompt_template,
-                        Err(e) => {
-                            return error::internal_server_error(e.to_string());
-                        }
-                    };
+            // the chunks are numbered, so that the answer cites them
+            let (context, point_citations) = crate::retrieval::cite(&scored_points);
+            citations = Some(point_citations);
 
-                    // insert rag context into chat request
-                    if let Err(e) = RagPromptBuilder::build(
-                        &mut chat_request.messages,
-                        &[context],
-                        prompt_template.has_system_prompt(),
-                        server_info.rag_config.policy,
-                    ) {
+            if chat_request.messages.is_empty() {
+                return error::internal_server_error("No message in the chat request.");
+            }
+
+            let prompt_template =
+                match llama_core::utils::chat_prompt_template(chat_request.model.as_deref()) {
+                    Ok(prompt_template) => prompt_template,
+                    Err(e) => {
                         return error::internal_server_error(e.to_string());
                     }
+                };
 
-                    println!("\n[+] Answer the user query with the context info ...");
-                }
+            // insert rag context into chat request
+            if let Err(e) = RagPromptBuilder::build(
+                &mut chat_request.messages,
+                &[context],
+                prompt_template.has_system_prompt(),
+                server_info.rag_config.policy,
+            ) {
+                return error::internal_server_error(e.to_string());
             }
+
+            println!("\n[+] Answer the user query with the context info ...");
         }
-        None => {
-            println!(
-                "    * No point retrieved (score < threshold {})",
-                server_info.qdrant_config.score_threshold
-            );
-            println!("\n[+] Answer the user query ...");
+    }
+
+    // the response reports how the context fits in the context window, and the sources the answer cites
+    let mut extra = Map::new();
+    if let Some(report) = context_report {
+        if let Ok(report) = serde_json::to_value(report) {
+            extra.insert("rag_context".to_string(), report);
+        }
+    }
+    if let Some(citations) = citations {
+        if let Ok(citations) = serde_json::to_value(citations) {
+            extra.insert("citations".to_string(), citations);
         }
     }
 
     // chat completion
     let res = match chat_request.stream {
-        Some(true) => chat_completions_stream(chat_request).await,
-        Some(false) | None => chat_completions(chat_request).await,
+        Some(true) => chat_completions_stream(chat_request, extra).await,
+        Some(false) | None => chat_completions(chat_request, extra).await,
     };
 
     print_log_end_separator(Some("*"), None);
@@ -398,6 +525,321 @@ pub(crate) async fn rag_query_handler(
     res
 }
 
+/// Fields of the requests to `/v1/chat/completions` and `/v1/retrieve` besides those of `ChatCompletionRequest`.
+#[derive(Debug, Default, Deserialize)]
+struct RetrieveOptions {
+    /// Restricts the retrieval to the chunks of some documents
+    #[serde(default)]
+    filter: Option<RetrieveFilter>,
+    /// Max number of retrieved points, up to `--qdrant-max-limit`. Overrides `--qdrant-limit`.
+    #[serde(default)]
+    rag_limit: Option<u64>,
+    /// Minimal score of the retrieved points. Overrides `--qdrant-score-threshold`.
+    #[serde(default)]
+    rag_score_threshold: Option<f32>,
+    /// Collection to search. Overrides `--qdrant-collection-name`.
+    #[serde(default)]
+    rag_collection: Option<String>,
+    /// How the points are retrieved. Overrides `--retrieval-mode`.
+    #[serde(default)]
+    rag_retrieval_mode: Option<RetrievalMode>,
+    /// Queries the points are retrieved for. Overrides `--rag-strategy`.
+    #[serde(default)]
+    rag_strategy: Option<RagStrategy>,
+    /// Whether the retrieved points are reranked by the chat model. Overrides `--rerank`.
+    #[serde(default)]
+    rag_rerank: Option<bool>,
+    /// Whether the last user message is rewritten into a standalone query before the retrieval. Overrides `--rewrite-query`.
+    #[serde(default)]
+    rag_rewrite_query: Option<bool>,
+    /// Whether the retrieved points are selected by maximal marginal relevance. Overrides `--mmr`.
+    #[serde(default)]
+    rag_mmr: Option<bool>,
+    /// Weight of the relevance against the diversity in the MMR selection, in `[0, 1]`. Overrides `--mmr-lambda`.
+    #[serde(default)]
+    rag_mmr_lambda: Option<f32>,
+}
+impl RetrieveOptions {
+    /// Override the retrieval settings of the server with those of the request. Without `rag_collection`, the collection the `model` of the request is routed to is searched.
+    fn resolve<'a>(
+        &'a self,
+        config: &'a QdrantConfig,
+        model: Option<&str>,
+    ) -> Result<Retrieval<'a>, ServerError> {
+        let (collection_name, by_model) = match (self.rag_collection.as_deref(), model) {
+            (Some(name), _) => {
+                config.check_collection(name)?;
+                (name, false)
+            }
+            (None, Some(model)) => match config.collection_of_model(model) {
+                Some(collection) => (collection.name.as_str(), true),
+                None => (config.collection_name.as_str(), false),
+            },
+            (None, None) => (config.collection_name.as_str(), false),
+        };
+        let collection = config.collection(collection_name);
+
+        let limit = match self.rag_limit {
+            Some(0) => {
+                return Err(ServerError::Operation(
+                    "`rag_limit` must be no less than 1.".to_string(),
+                ))
+            }
+            Some(limit) if limit > config.max_limit => {
+                println!(
+                    "    * rag_limit {} is capped to the max limit {}",
+                    limit, config.max_limit
+                );
+                config.max_limit
+            }
+            Some(limit) => limit,
+            None => collection
+                .and_then(|collection| collection.limit)
+                .unwrap_or(config.limit),
+        };
+
+        let score_threshold = match self.rag_score_threshold {
+            Some(score_threshold) if !score_threshold.is_finite() => {
+                return Err(ServerError::Operation(format!(
+                    "Invalid `rag_score_threshold`: {}",
+                    score_threshold
+                )))
+            }
+            Some(score_threshold) => score_threshold,
+            None => collection
+                .and_then(|collection| collection.score_threshold)
+                .unwrap_or(config.score_threshold),
+        };
+
+        let mmr_lambda = match self.rag_mmr_lambda {
+            Some(lambda) if !(0.0..=1.0).contains(&lambda) => {
+                return Err(ServerError::Operation(format!(
+                    "`rag_mmr_lambda` must be in [0, 1], but it is {}.",
+                    lambda
+                )))
+            }
+            Some(lambda) => lambda,
+            None => config.mmr_lambda,
+        };
+
+        Ok(Retrieval {
+            collection_name,
+            limit: limit as usize,
+            score_threshold,
+            mode: self.rag_retrieval_mode.unwrap_or(config.retrieval_mode),
+            rewrite_query: self.rag_rewrite_query.unwrap_or(config.rewrite_query),
+            strategy: self.rag_strategy.unwrap_or(config.strategy),
+            rerank: self.rag_rerank.unwrap_or(config.rerank),
+            mmr: self.rag_mmr.unwrap_or(config.mmr),
+            mmr_lambda,
+            // a request retrieves no more points than the max limit, candidates included
+            candidates: config.rerank_candidates.min(config.max_limit) as usize,
+            filter: self.filter.as_ref(),
+            by_model,
+        })
+    }
+}
+
+/// Retrieval settings of a request.
+#[derive(Debug)]
+struct Retrieval<'a> {
+    collection_name: &'a str,
+    limit: usize,
+    score_threshold: f32,
+    mode: RetrievalMode,
+    rewrite_query: bool,
+    strategy: RagStrategy,
+    rerank: bool,
+    mmr: bool,
+    mmr_lambda: f32,
+    /// Number of points retrieved for the reranking or the MMR selection, no more than the max limit
+    candidates: usize,
+    filter: Option<&'a RetrieveFilter>,
+    /// Whether the collection is picked by the model name of the request
+    by_model: bool,
+}
+
+/// Retrieve the points relevant to `query_text`, the last user message of `messages`, with the given settings.
+///
+/// If query rewriting is enabled, the chat `model` first rewrites the query into a standalone query using the earlier messages. The strategy may have the model generate more queries, whose points are merged with those of the query. If reranking is enabled, the model ranks the candidates by relevance. If MMR is enabled, the points are then selected by maximal marginal relevance, so that they are not near duplicates of each other.
+async fn retrieve_context(
+    query_text: &str,
+    messages: &[ChatCompletionRequestMessage],
+    user: Option<String>,
+    retrieval: &Retrieval<'_>,
+    model: Option<&str>,
+) -> Result<vector_store::RetrieveObject, ServerError> {
+    // follow-up questions refer to the earlier turns
+    let rewritten_query = match retrieval.rewrite_query {
+        true => Some(crate::retrieval::rewrite_query(model, messages, query_text).await),
+        false => None,
+    };
+    let query_text = rewritten_query.as_deref().unwrap_or(query_text);
+
+    // paraphrases or a hypothetical answer find the chunks the wording of the user misses
+    let expanded_queries =
+        crate::retrieval::expand_query(retrieval.strategy, model, query_text).await;
+
+    // the reranking and the MMR selection pick from more points than the limit
+    let limit = match retrieval.rerank || retrieval.mmr {
+        true => retrieval.limit.max(retrieval.candidates),
+        false => retrieval.limit,
+    };
+
+    println!("\n[+] Retrieving context ...");
+    println!("    * collection: {}", retrieval.collection_name);
+    println!("    * limit: {}", retrieval.limit);
+    println!("    * score threshold: {}", retrieval.score_threshold);
+    println!("    * retrieval mode: {}", retrieval.mode);
+    println!("    * strategy: {}", retrieval.strategy);
+    if retrieval.rerank || retrieval.mmr {
+        println!("    * candidates: {}", limit);
+    }
+    if retrieval.mmr {
+        println!("    * mmr lambda: {}", retrieval.mmr_lambda);
+    }
+    if let Some(filter) = retrieval.filter.and_then(RetrieveFilter::to_filter) {
+        println!("    * filter: {}", filter.to_json());
+    }
+
+    // the points of every query, the user query first
+    let mut lists = vec![];
+    for text in std::iter::once(query_text).chain(expanded_queries.iter().map(String::as_str)) {
+        println!("\n[+] Computing embeddings for query ...");
+        println!("    * query: {}", text);
+
+        let embedding = embed_query(text, user.clone()).await?;
+        let query = vector_store::Query {
+            text,
+            embedding: &embedding,
+        };
+        let ro = vector_store::retrieve(
+            query,
+            retrieval.collection_name,
+            limit,
+            Some(retrieval.score_threshold),
+            retrieval.filter,
+            retrieval.mode,
+            retrieval.mmr,
+        )
+        .await?;
+        lists.push(ro.points.unwrap_or_default());
+    }
+
+    let mut points = crate::retrieval::merge(lists, limit);
+    if !expanded_queries.is_empty() {
+        println!("    * {} point(s) merged", points.len());
+    }
+    if !points.is_empty() {
+        if retrieval.rerank {
+            // the MMR selection needs all the reranked candidates
+            let keep = match retrieval.mmr {
+                true => points.len(),
+                false => retrieval.limit,
+            };
+            points = crate::retrieval::rerank(model, query_text, points, keep).await;
+        }
+        if retrieval.mmr {
+            points = crate::retrieval::mmr(points, retrieval.limit, retrieval.mmr_lambda);
+        }
+    }
+
+    Ok(vector_store::RetrieveObject {
+        points: match points.is_empty() {
+            true => None,
+            false => Some(points),
+        },
+        limit: retrieval.limit,
+        score_threshold: retrieval.score_threshold,
+        rewritten_query,
+        expanded_queries: match expanded_queries.is_empty() {
+            true => None,
+            false => Some(expanded_queries),
+        },
+    })
+}
+
+/// Get the text of the last message of a chat request, which must be a user message.
+fn last_user_query(messages: &[ChatCompletionRequestMessage]) -> Result<String, ServerError> {
+    match messages.last() {
+        Some(ChatCompletionRequestMessage::User(user_message)) => match user_message.content() {
+            ChatCompletionUserMessageContent::Text(text) => Ok(text.clone()),
+            _ => Err(ServerError::Operation(
+                "The last message must be a text content user message".to_string(),
+            )),
+        },
+        Some(_) => Err(ServerError::Operation(
+            "The last message must be a user message".to_string(),
+        )),
+        None => Err(ServerError::Operation(
+            "Messages should not be empty".to_string(),
+        )),
+    }
+}
+
+/// Compute the embedding of a query with the embedding model.
+async fn embed_query(query_text: &str, user: Option<String>) -> Result<Vec<f32>, ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+
+    // get the available embedding models
+    let embedding_model_names = llama_core::utils::embedding_model_names()
+        .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    // create a embedding request
+    let embedding_request = EmbeddingRequest {
+        model: embedding_model_names[0].clone(),
+        input: vec![query_text.to_string()],
+        encoding_format: None,
+        user,
+    };
+
+    if let Ok(request_str) = serde_json::to_string_pretty(&embedding_request) {
+        println!("    * embedding request (json):\n\n{}", request_str);
+    }
+
+    let rag_embedding_request = RagEmbeddingRequest {
+        embedding_request,
+        qdrant_url: server_info.qdrant_config.url.clone(),
+        qdrant_collection_name: server_info.qdrant_config.collection_name.clone(),
+    };
+
+    // compute embeddings for query
+    let embedding_response = llama_core::rag::rag_query_to_embeddings(&rag_embedding_request)
+        .await
+        .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    match embedding_response.data.first() {
+        Some(embedding) => Ok(embedding.embedding.iter().map(|x| *x as f32).collect()),
+        None => Err(ServerError::Operation("No embeddings returned".to_string())),
+    }
+}
+
+/// Build the prompt of the chat model for the messages along with a context without chunk, i.e. the prompt the retrieved chunks are added to.
+fn prompt_without_context(
+    messages: &[ChatCompletionRequestMessage],
+    model: Option<&str>,
+    policy: MergeRagContextPolicy,
+) -> Result<String, ServerError> {
+    let prompt_template = llama_core::utils::chat_prompt_template(model)
+        .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    let mut messages = messages.to_vec();
+    let (context, _) = crate::retrieval::cite(&[]);
+    RagPromptBuilder::build(
+        &mut messages,
+        &[context],
+        prompt_template.has_system_prompt(),
+        policy,
+    )
+    .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    ChatPrompt::from(prompt_template)
+        .build(&mut messages)
+        .map_err(|e| ServerError::Operation(e.to_string()))
+}
+
 #[derive(Debug, Default)]
 struct RagPromptBuilder;
 impl MergeRagContext for RagPromptBuilder {
@@ -554,12 +996,8 @@ pub(crate) async fn files_handler(req: Request<Body>) -> Result<Response<Body>,
                     }
                 };
 
-                if !((filename).to_lowercase().ends_with(".txt")
-                    || (filename).to_lowercase().ends_with(".md"))
-                {
-                    return error::internal_server_error(
-                        "Failed to upload the target file. Only files with 'txt' and 'md' extensions are supported.",
-                    );
+                if !ingest::is_supported(&filename) {
+                    return error::internal_server_error(ingest::unsupported_message());
                 }
 
                 let mut buffer = Vec::new();
@@ -581,11 +1019,23 @@ pub(crate) async fn files_handler(req: Request<Body>) -> Result<Response<Body>,
                 // save the file
                 let path = Path::new("archives");
                 if !path.exists() {
-                    fs::create_dir(path).unwrap();
+                    if let Err(e) = fs::create_dir(path) {
+                        return error::internal_server_error(format!(
+                            "Failed to create the archive directory {}. {}",
+                            path.display(),
+                            e
+                        ));
+                    }
                 }
                 let file_path = path.join(&id);
                 if !file_path.exists() {
-                    fs::create_dir(&file_path).unwrap();
+                    if let Err(e) = fs::create_dir(&file_path) {
+                        return error::internal_server_error(format!(
+                            "Failed to create the archive directory {}. {}",
+                            file_path.display(),
+                            e
+                        ));
+                    }
                 }
                 let mut file = match File::create(file_path.join(&filename)) {
                     Ok(file) => file,
@@ -596,7 +1046,12 @@ pub(crate) async fn files_handler(req: Request<Body>) -> Result<Response<Body>,
                         ));
                     }
                 };
-                file.write_all(&buffer[..]).unwrap();
+                if let Err(e) = file.write_all(&buffer[..]) {
+                    return error::internal_server_error(format!(
+                        "Failed to write archive document {}. {}",
+                        &filename, e
+                    ));
+                }
 
                 let created_at = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
                     Ok(n) => n.as_secs(),
@@ -615,6 +1070,13 @@ pub(crate) async fn files_handler(req: Request<Body>) -> Result<Response<Body>,
                     purpose: "assistants".to_string(),
                 });
 
+                if let Some(fo) = &file_object {
+                    let record = registry::FileRecord::new(fo, registry::checksum(&buffer));
+                    if let Err(e) = registry::add(record) {
+                        return error::internal_server_error(e.to_string());
+                    }
+                }
+
                 break;
             }
         }
@@ -650,89 +1112,647 @@ pub(crate) async fn files_handler(req: Request<Body>) -> Result<Response<Body>,
             ),
         }
     } else if req.method() == Method::GET {
-        error::internal_server_error("Not implemented for listing files.")
+        println!("\n[+] Listing archived files ...");
+
+        let list_files_response = ListFilesResponse {
+            object: "list".to_string(),
+            data: match registry::list(None) {
+                Ok(records) => records,
+                Err(e) => return error::internal_server_error(e.to_string()),
+            },
+        };
+
+        println!("    * Number of files: {}", list_files_response.data.len());
+
+        json_response(&list_files_response)
     } else {
         error::internal_server_error("Invalid HTTP Method.")
     }
 }
 
-pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-    println!("\n[+] Running chunks handler ...");
+/// Response of `GET /v1/files`.
+#[derive(Debug, Serialize)]
+struct ListFilesResponse {
+    object: String,
+    data: Vec<registry::FileRecord>,
+}
 
-    // parse request
-    let body_bytes = to_bytes(req.body_mut()).await?;
-    let chunks_request: ChunksRequest = match serde_json::from_slice(&body_bytes) {
-        Ok(chunks_request) => chunks_request,
-        Err(e) => {
-            return error::bad_request(format!("Fail to parse chunks request: {msg}", msg = e));
-        }
+/// Response of `DELETE /v1/files/{id}`.
+#[derive(Debug, Serialize)]
+struct DeleteFileStatus {
+    id: String,
+    object: String,
+    deleted: bool,
+}
+
+/// Handle `GET /v1/files/{id}`, `GET /v1/files/{id}/content`, `PUT /v1/files/{id}` and `DELETE /v1/files/{id}`.
+///
+/// An archive uploaded to `/v1/create/rag` holds several files under the same id. The `filename` query parameter selects one of them.
+pub(crate) async fn file_handler(
+    req: Request<Body>,
+    chunk_capacity: usize,
+) -> Result<Response<Body>, hyper::Error> {
+    let path = req.uri().path().trim_end_matches('/');
+    let (id, content) = match path.trim_start_matches("/v1/files/").split_once('/') {
+        Some((id, "content")) => (id.to_string(), true),
+        Some(_) => return error::invalid_endpoint(path),
+        None => (path.trim_start_matches("/v1/files/").to_string(), false),
     };
 
-    println!("[+] Detecting the target file ...");
-    // check if the archives directory exists
-    let path = Path::new("archives");
-    if !path.exists() {
-        return error::internal_server_error("The `archives` directory does not exist.");
+    // the id names a directory under `archives`
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        return error::bad_request(format!("Invalid file id: {}", &id));
     }
-
-    // check if the archive id exists
-    let archive_path = path.join(&chunks_request.id);
-    if !archive_path.exists() {
-        let message = format!("Not found archive id: {}", &chunks_request.id);
-        return error::internal_server_error(message);
+    let archive_path = Path::new("archives").join(&id);
+    if !archive_path.is_dir() {
+        return error::not_found(format!("Not found archive id: {}", &id));
     }
 
-    // check if the file exists
-    let file_path = archive_path.join(&chunks_request.filename);
-    if !file_path.exists() {
-        let message = format!(
-            "Not found file: {} in archive id: {}",
-            &chunks_request.filename, &chunks_request.id
-        );
-        return error::internal_server_error(message);
-    }
-    println!(
-        "    * Found {}/{}",
-        &chunks_request.id, &chunks_request.filename
-    );
+    if req.method() == Method::DELETE && !content {
+        println!("\n[+] Deleting archived file {} ...", &id);
 
-    // get the extension of the archived file
-    let extension = match file_path.extension().and_then(std::ffi::OsStr::to_str) {
-        Some(extension) => extension,
-        None => {
-            return error::internal_server_error(format!(
-                "Failed to get the extension of the archived `{}`.",
-                &chunks_request.filename
-            ));
-        }
-    };
+        let server_info = match SERVER_INFO.get() {
+            Some(server_info) => server_info,
+            None => {
+                return error::internal_server_error("The server info is not set.");
+            }
+        };
 
-    // open the file
-    let mut file = match File::open(&file_path) {
-        Ok(file) => file,
-        Err(e) => {
+        // the documents of the archive may be embedded into different collections
+        let mut collections = match registry::list(Some(&id)) {
+            Ok(records) => records
+                .into_iter()
+                .filter_map(|record| record.collection)
+                .collect::<Vec<String>>(),
+            Err(e) => return error::internal_server_error(e.to_string()),
+        };
+        collections.push(server_info.qdrant_config.collection_name.clone());
+        collections.sort();
+        collections.dedup();
+
+        // remove the points first, so that the file is kept if they cannot be removed
+        for collection_name in collections.iter() {
+            if let Err(e) = vector_store::delete_file(collection_name, &id, None).await {
+                return error::internal_server_error(e.to_string());
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(&archive_path) {
             return error::internal_server_error(format!(
-                "Failed to open `{}`. {}",
-                &chunks_request.filename, e
+                "Failed to remove archive {}. {}",
+                &id, e
             ));
         }
-    };
+        if let Err(e) = registry::remove(&id, None) {
+            return error::internal_server_error(e.to_string());
+        }
 
-    // read the file
-    let mut contents = String::new();
-    if let Err(e) = file.read_to_string(&mut contents) {
-        return error::internal_server_error(format!(
-            "Failed to read `{}`. {}",
-            &chunks_request.filename, e
-        ));
+        println!("[+] File deleted successfully.\n");
+
+        return json_response(&DeleteFileStatus {
+            id,
+            object: "file".to_string(),
+            deleted: true,
+        });
+    } else if req.method() != Method::GET && (req.method() != Method::PUT || content) {
+        return error::internal_server_error("Invalid HTTP Method.");
     }
 
-    match llama_core::rag::chunk_text(&contents, extension, chunks_request.chunk_capacity) {
-        Ok(chunks) => {
-            let chunks_response = ChunksResponse {
-                id: chunks_request.id,
+    let filename = req.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "filename")
+            .map(|(_, value)| value.to_string())
+    });
+    let mut files = match registry::list(Some(&id)) {
+        Ok(files) => files,
+        Err(e) => return error::internal_server_error(e.to_string()),
+    };
+    if let Some(filename) = &filename {
+        files.retain(|file| &file.filename == filename);
+    }
+    let file_object = match files.len() {
+        0 => {
+            return error::not_found(format!(
+                "Not found file: {} in archive id: {}",
+                filename.unwrap_or_default(),
+                &id
+            ))
+        }
+        1 => files.remove(0),
+        n => {
+            return error::bad_request(format!(
+                "The archive {} holds {} files. Select one with the `filename` query parameter.",
+                &id, n
+            ))
+        }
+    };
+
+    if req.method() == Method::PUT {
+        return reindex_file(req, file_object, chunk_capacity).await;
+    }
+
+    if !content {
+        return json_response(&file_object);
+    }
+
+    match fs::read(archive_path.join(&file_object.filename)) {
+        Ok(bytes) => {
+            let mime = mime_guess::from_path(&file_object.filename).first_or_octet_stream();
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .header(hyper::header::CONTENT_TYPE, mime.to_string())
+                .body(Body::from(bytes));
+            match result {
+                Ok(response) => Ok(response),
+                Err(e) => error::internal_server_error(e.to_string()),
+            }
+        }
+        Err(e) => {
+            error::internal_server_error(format!("Failed to read {}. {}", &file_object.filename, e))
+        }
+    }
+}
+
+/// Report of `PUT /v1/files/{id}`.
+#[derive(Debug, Serialize)]
+struct ReindexReport {
+    id: String,
+    object: String,
+    filename: String,
+    bytes: u64,
+    /// Number of chunks of the new version
+    chunks: usize,
+    /// Number of chunks whose points are kept
+    unchanged: usize,
+    /// Number of chunks embedded
+    embedded: usize,
+    /// Number of points of vanished chunks deleted
+    deleted: usize,
+}
+
+/// Replace the contents of an archived document with the uploaded file, then re-index it.
+///
+/// Only the chunks whose text changed are embedded. The points of unchanged chunks are kept, and those of the chunks that disappeared are deleted.
+async fn reindex_file(
+    req: Request<Body>,
+    record: registry::FileRecord,
+    chunk_capacity: usize,
+) -> Result<Response<Body>, hyper::Error> {
+    println!(
+        "\n[+] Re-indexing archived file {}/{} ...",
+        &record.id, &record.filename
+    );
+
+    let boundary = "boundary=";
+
+    let boundary = req.headers().get("content-type").and_then(|ct| {
+        let ct = ct.to_str().ok()?;
+        let idx = ct.find(boundary)?;
+        Some(ct[idx + boundary.len()..].to_string())
+    });
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => {
+            return error::bad_request("The new version must be uploaded as multipart form data.")
+        }
+    };
+
+    let req_body = req.into_body();
+    let body_bytes = to_bytes(req_body).await?;
+    let cursor = Cursor::new(body_bytes.to_vec());
+
+    let mut multipart = Multipart::with_body(cursor, boundary);
+
+    let mut buffer = None;
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        if &*field.headers.name == "file" {
+            let mut bytes = Vec::new();
+            if let Err(e) = field.data.read_to_end(&mut bytes) {
+                return error::internal_server_error(format!(
+                    "Failed to read the target file. {}",
+                    e
+                ));
+            }
+
+            buffer = Some(bytes);
+            break;
+        }
+    }
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => return error::bad_request("Not found the `file` field."),
+    };
+
+    // the document keeps its name, so that its id and filename stay valid
+    let file_path = Path::new("archives")
+        .join(&record.id)
+        .join(&record.filename);
+    let previous = match fs::read(&file_path) {
+        Ok(previous) => previous,
+        Err(e) => {
+            return error::internal_server_error(format!(
+                "Failed to read {}. {}",
+                &record.filename, e
+            ))
+        }
+    };
+    if let Err(e) = fs::write(&file_path, &buffer) {
+        return error::internal_server_error(format!(
+            "Failed to write {}. {}",
+            &record.filename, e
+        ));
+    }
+
+    let (report, embedding_model) = match reindex(&record, &file_path, chunk_capacity).await {
+        Ok(result) => result,
+        Err(e) => {
+            // the previous version stays archived along with its points
+            let _ = fs::write(&file_path, &previous);
+            return error::internal_server_error(e.to_string());
+        }
+    };
+
+    let server_info = match SERVER_INFO.get() {
+        Some(server_info) => server_info,
+        None => return error::internal_server_error("The server info is not set."),
+    };
+    let collection = record
+        .collection
+        .clone()
+        .unwrap_or_else(|| server_info.qdrant_config.collection_name.clone());
+    let record = registry::FileRecord {
+        bytes: buffer.len() as u64,
+        checksum: registry::checksum(&buffer),
+        chunks: Some(report.chunks),
+        embedding_model: Some(embedding_model),
+        collection: Some(collection),
+        ..record
+    };
+    if let Err(e) = registry::add(record) {
+        return error::internal_server_error(e.to_string());
+    }
+
+    println!(
+        "[+] File re-indexed: {} unchanged, {} embedded, {} deleted.\n",
+        report.unchanged, report.embedded, report.deleted
+    );
+
+    json_response(&report)
+}
+
+/// Re-index the new version of an archived document at `file_path`. Returns the report and the name of the embedding model.
+async fn reindex(
+    record: &registry::FileRecord,
+    file_path: &Path,
+    chunk_capacity: usize,
+) -> Result<(ReindexReport, String), ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+    // the document stays in the collection it was embedded into
+    let collection_name = record
+        .collection
+        .as_ref()
+        .unwrap_or(&server_info.qdrant_config.collection_name);
+
+    let mut chunks = ingest::chunk_file(file_path, chunk_capacity)?;
+    if chunks.is_empty() {
+        return Err(ServerError::Operation(format!(
+            "No text found in `{}`.",
+            &record.filename
+        )));
+    }
+
+    // record the source document of each chunk
+    ingest::set_document(
+        &mut chunks,
+        &document_meta(
+            &record.id,
+            &record.filename,
+            record.created_at,
+            &record.tags,
+        ),
+    );
+
+    // the vectors of another model, or of another collection, cannot be reused
+    let model = embedding_model_name()?;
+    let reusable = record.embedding_model.as_ref() == Some(&model)
+        && record.collection.as_ref() == Some(collection_name);
+
+    // the points of the previous version, by the checksums of the texts of their chunks
+    let mut previous: HashMap<String, Vec<vector_store::StoredPoint>> = HashMap::new();
+    let mut deleted = vec![];
+    for point in
+        vector_store::document_points(collection_name, &record.id, &record.filename).await?
+    {
+        match &point.chunk_hash {
+            Some(hash) if reusable && !point.vector.is_empty() => {
+                previous.entry(hash.clone()).or_default().push(point)
+            }
+            _ => deleted.push(point.id),
+        }
+    }
+
+    let mut points = vec![];
+    let mut changed = vec![];
+    for chunk in chunks.iter() {
+        let hash = registry::checksum(chunk.text.as_bytes());
+        match previous.get_mut(&hash).and_then(Vec::pop) {
+            Some(point) => points.push((point.id, chunk, point.vector)),
+            None => changed.push(chunk.clone()),
+        }
+    }
+    let unchanged = points.len();
+    deleted.extend(previous.into_values().flatten().map(|point| point.id));
+
+    println!("    * Number of chunks: {}", chunks.len());
+    println!("    * Number of changed chunks: {}", changed.len());
+
+    let mut embeddings = vec![];
+    if !changed.is_empty() {
+        embeddings = compute_embeddings(&changed).await?.data;
+    }
+    for embedding in embeddings {
+        let chunk = changed.get(embedding.index as usize).ok_or_else(|| {
+            ServerError::Operation(format!(
+                "Not found the chunk of embedding {}.",
+                embedding.index
+            ))
+        })?;
+
+        points.push((
+            vector_store::PointId::Uuid(uuid::Uuid::new_v4().to_string()),
+            chunk,
+            embedding.embedding.iter().map(|x| *x as f32).collect(),
+        ));
+    }
+
+    // the points of unchanged chunks are upserted as well, as their metadata may have moved
+    let embedded = points.len() - unchanged;
+    vector_store::upsert_chunks(collection_name, points).await?;
+
+    let report = ReindexReport {
+        id: record.id.clone(),
+        object: "file".to_string(),
+        filename: record.filename.clone(),
+        bytes: fs::metadata(file_path).map(|m| m.len()).unwrap_or_default(),
+        chunks: chunks.len(),
+        unchanged,
+        embedded,
+        deleted: deleted.len(),
+    };
+
+    vector_store::delete_points(collection_name, deleted).await?;
+
+    Ok((report, model))
+}
+
+/// A collection of `/v1/collections`.
+#[derive(Debug, Serialize)]
+struct CollectionObject {
+    object: String,
+    #[serde(flatten)]
+    info: vector_store::CollectionInfo,
+    /// Description of the collection in the collections config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// Whether the requests that pick no collection use the collection
+    default: bool,
+}
+impl CollectionObject {
+    fn new(config: &QdrantConfig, info: vector_store::CollectionInfo) -> Self {
+        Self {
+            object: "collection".to_string(),
+            description: config
+                .collection(&info.name)
+                .and_then(|collection| collection.description.clone()),
+            default: info.name == config.collection_name,
+            info,
+        }
+    }
+}
+
+/// Response of `GET /v1/collections`.
+#[derive(Debug, Serialize)]
+struct ListCollectionsResponse {
+    object: String,
+    data: Vec<CollectionObject>,
+}
+
+/// Request of `POST /v1/collections`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreateCollectionRequest {
+    name: String,
+    /// Distance function of the vectors. Defaults to `Cosine`.
+    #[serde(default)]
+    distance: vector_store::Distance,
+}
+
+/// Response of `DELETE /v1/collections/{name}`.
+#[derive(Debug, Serialize)]
+struct DeleteCollectionStatus {
+    name: String,
+    object: String,
+    deleted: bool,
+}
+
+/// Handle `GET /v1/collections` and `POST /v1/collections`.
+///
+/// The vectors of a created collection have the dimension of the embedding model.
+pub(crate) async fn collections_handler(
+    mut req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let server_info = match SERVER_INFO.get() {
+        Some(server_info) => server_info,
+        None => return error::internal_server_error("The server info is not set."),
+    };
+    let qdrant_config = &server_info.qdrant_config;
+
+    if req.method() == Method::GET {
+        let collections = match vector_store::list_collections().await {
+            Ok(collections) => collections,
+            Err(e) => return error::internal_server_error(e.to_string()),
+        };
+
+        json_response(&ListCollectionsResponse {
+            object: "list".to_string(),
+            data: collections
+                .into_iter()
+                .map(|info| CollectionObject::new(qdrant_config, info))
+                .collect(),
+        })
+    } else if req.method() == Method::POST {
+        println!("\n[+] Creating collection ...");
+
+        let body_bytes = to_bytes(req.body_mut()).await?;
+        let create_request: CreateCollectionRequest = match serde_json::from_slice(&body_bytes) {
+            Ok(create_request) => create_request,
+            Err(e) => {
+                return error::bad_request(format!(
+                    "Fail to parse create collection request: {msg}",
+                    msg = e
+                ));
+            }
+        };
+        if let Err(e) = qdrant_config.check_collection(&create_request.name) {
+            return error::bad_request(e.to_string());
+        }
+        match vector_store::inspect_collection(&create_request.name).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return error::bad_request(format!(
+                    "The collection {} already exists.",
+                    &create_request.name
+                ))
+            }
+            Err(e) => return error::internal_server_error(e.to_string()),
+        }
+
+        // the collection must fit the vectors of the embedding model
+        let vector_size = match embedding_model_name().and_then(|model| {
+            llama_core::embeddings::dimension(Some(&model))
+                .map_err(|e| ServerError::Operation(e.to_string()))
+        }) {
+            Ok(vector_size) => vector_size,
+            Err(e) => return error::internal_server_error(e.to_string()),
+        };
+
+        println!("    * Collection name: {}", &create_request.name);
+        println!("    * Dimension: {}", vector_size);
+        println!("    * Distance: {:?}", create_request.distance);
+
+        let info = match vector_store::create_collection(
+            &create_request.name,
+            vector_size,
+            create_request.distance,
+        )
+        .await
+        {
+            Ok(info) => info,
+            Err(e) => return error::internal_server_error(e.to_string()),
+        };
+
+        println!("[+] Collection created successfully.\n");
+
+        json_response(&CollectionObject::new(qdrant_config, info))
+    } else {
+        error::internal_server_error("Invalid HTTP Method.")
+    }
+}
+
+/// Handle `GET /v1/collections/{name}` and `DELETE /v1/collections/{name}`.
+///
+/// The archived files embedded into a dropped collection are kept, and are recorded as not embedded.
+pub(crate) async fn collection_handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    let path = req.uri().path().trim_end_matches('/');
+    let name = path.trim_start_matches("/v1/collections/").to_string();
+    if !collections::is_valid_collection_name(&name) {
+        return error::bad_request(format!("Invalid collection name: {}", &name));
+    }
+
+    let server_info = match SERVER_INFO.get() {
+        Some(server_info) => server_info,
+        None => return error::internal_server_error("The server info is not set."),
+    };
+    let qdrant_config = &server_info.qdrant_config;
+
+    if req.method() == Method::GET {
+        match vector_store::inspect_collection(&name).await {
+            Ok(Some(info)) => json_response(&CollectionObject::new(qdrant_config, info)),
+            Ok(None) => error::not_found(format!("Not found collection: {}", &name)),
+            Err(e) => error::internal_server_error(e.to_string()),
+        }
+    } else if req.method() == Method::DELETE {
+        println!("\n[+] Dropping collection {} ...", &name);
+
+        match vector_store::drop_collection(&name).await {
+            Ok(true) => {}
+            Ok(false) => return error::not_found(format!("Not found collection: {}", &name)),
+            Err(e) => return error::internal_server_error(e.to_string()),
+        }
+        match registry::clear_collection(&name) {
+            Ok(count) => println!("    * Number of files no longer embedded: {}", count),
+            Err(e) => return error::internal_server_error(e.to_string()),
+        }
+
+        println!("[+] Collection dropped successfully.\n");
+
+        json_response(&DeleteCollectionStatus {
+            name,
+            object: "collection".to_string(),
+            deleted: true,
+        })
+    } else {
+        error::internal_server_error("Invalid HTTP Method.")
+    }
+}
+
+fn json_response(value: &impl Serialize) -> Result<Response<Body>, hyper::Error> {
+    match serde_json::to_string(value) {
+        Ok(s) => {
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .body(Body::from(s));
+            match result {
+                Ok(response) => Ok(response),
+                Err(e) => error::internal_server_error(e.to_string()),
+            }
+        }
+        Err(e) => error::internal_server_error(format!("Fail to serialize response. {}", e)),
+    }
+}
+
+pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    println!("\n[+] Running chunks handler ...");
+
+    // parse request
+    let body_bytes = to_bytes(req.body_mut()).await?;
+    let chunks_request: ChunksRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(chunks_request) => chunks_request,
+        Err(e) => {
+            return error::bad_request(format!("Fail to parse chunks request: {msg}", msg = e));
+        }
+    };
+
+    println!("[+] Detecting the target file ...");
+    // check if the archives directory exists
+    let path = Path::new("archives");
+    if !path.exists() {
+        return error::internal_server_error("The `archives` directory does not exist.");
+    }
+
+    // check if the archive id exists
+    let archive_path = path.join(&chunks_request.id);
+    if !archive_path.exists() {
+        let message = format!("Not found archive id: {}", &chunks_request.id);
+        return error::internal_server_error(message);
+    }
+
+    // check if the file exists
+    let file_path = archive_path.join(&chunks_request.filename);
+    if !file_path.exists() {
+        let message = format!(
+            "Not found file: {} in archive id: {}",
+            &chunks_request.filename, &chunks_request.id
+        );
+        return error::internal_server_error(message);
+    }
+    println!(
+        "    * Found {}/{}",
+        &chunks_request.id, &chunks_request.filename
+    );
+
+    match ingest::chunk_file(&file_path, chunks_request.chunk_capacity) {
+        Ok(chunks) => {
+            let chunks_response = ChunksResponse {
+                id: chunks_request.id,
                 filename: chunks_request.filename,
-                chunks,
+                chunks: chunks.into_iter().map(|chunk| chunk.text).collect(),
             };
 
             println!("[+] File chunked successfully.\n");
@@ -765,8 +1785,15 @@ pub(crate) async fn doc_to_embeddings(
     req: Request<Body>,
     chunk_capacity: usize,
 ) -> Result<Response<Body>, hyper::Error> {
+    // how an upload identical to an archived document is handled, and the archived document it replaced
+    let mut on_duplicate = OnDuplicate::Skip;
+    let mut replaced: Option<registry::FileRecord> = None;
+    // the other fields of the form tag the document
+    let mut tags = BTreeMap::new();
+    let mut collection = None;
+
     // upload the target rag document
-    let file_object = if req.method() == Method::POST {
+    let (file_object, collection_name) = if req.method() == Method::POST {
         let boundary = "boundary=";
 
         let boundary = req.headers().get("content-type").and_then(|ct| {
@@ -781,95 +1808,228 @@ pub(crate) async fn doc_to_embeddings(
 
         let mut multipart = Multipart::with_body(cursor, boundary.unwrap());
 
-        let mut file_object: Option<FileObject> = None;
+        let mut upload: Option<(String, Vec<u8>)> = None;
         while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
-            if &*field.headers.name == "file" {
-                let filename = match field.headers.filename {
-                    Some(filename) => filename,
-                    None => {
-                        return error::internal_server_error(
-                            "Failed to upload the target file. The filename is not provided.",
-                        );
-                    }
-                };
+            match &*field.headers.name {
+                "file" => {
+                    let filename = match field.headers.filename {
+                        Some(filename) => filename,
+                        None => {
+                            return error::internal_server_error(
+                                "Failed to upload the target file. The filename is not provided.",
+                            );
+                        }
+                    };
 
-                if !((filename).to_lowercase().ends_with(".txt")
-                    || (filename).to_lowercase().ends_with(".md"))
-                {
-                    return error::internal_server_error(
-                        "Failed to upload the target file. Only files with 'txt' and 'md' extensions are supported.",
-                    );
-                }
+                    if !ingest::is_supported(&filename) && !ingest::archive::is_archive(&filename) {
+                        return error::internal_server_error(format!(
+                            "{} Archives with {} extensions are supported as well.",
+                            ingest::unsupported_message(),
+                            ingest::archive::ARCHIVE_EXTENSIONS
+                                .iter()
+                                .map(|ext| format!("'{}'", ext))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        ));
+                    }
 
-                let mut buffer = Vec::new();
-                let size_in_bytes = match field.data.read_to_end(&mut buffer) {
-                    Ok(size_in_bytes) => size_in_bytes,
-                    Err(e) => {
+                    let mut buffer = Vec::new();
+                    if let Err(e) = field.data.read_to_end(&mut buffer) {
                         return error::internal_server_error(format!(
                             "Failed to read the target file. {}",
                             e
                         ));
                     }
-                };
-
-                // create a unique file id
-                let id = format!("file_{}", uuid::Uuid::new_v4());
 
-                // save the file
-                let path = Path::new("archives");
-                if !path.exists() {
-                    fs::create_dir(path).unwrap();
+                    upload = Some((filename, buffer));
                 }
-                let file_path = path.join(&id);
-                if !file_path.exists() {
-                    fs::create_dir(&file_path).unwrap();
-                }
-                let mut file = match File::create(file_path.join(&filename)) {
-                    Ok(file) => file,
-                    Err(e) => {
-                        return error::internal_server_error(format!(
-                            "Failed to create archive document {}. {}",
-                            &filename, e
+                "on_duplicate" => {
+                    let mut value = String::new();
+                    if let Err(e) = field.data.read_to_string(&mut value) {
+                        return error::bad_request(format!(
+                            "Failed to read the `on_duplicate` field. {}",
+                            e
                         ));
                     }
-                };
-                file.write_all(&buffer[..]).unwrap();
 
-                let created_at = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
-                    Ok(n) => n.as_secs(),
-                    Err(_) => {
-                        return error::internal_server_error("Failed to get the current time.")
+                    on_duplicate = match value.trim() {
+                        "skip" => OnDuplicate::Skip,
+                        "replace" => OnDuplicate::Replace,
+                        value => {
+                            return error::bad_request(format!(
+                                "Invalid value of the `on_duplicate` field: {}. Expected `skip` or `replace`.",
+                                value
+                            ))
+                        }
+                    };
+                }
+                "collection" => {
+                    let mut value = String::new();
+                    if let Err(e) = field.data.read_to_string(&mut value) {
+                        return error::bad_request(format!(
+                            "Failed to read the `collection` field. {}",
+                            e
+                        ));
                     }
-                };
 
-                // create a file object
-                file_object = Some(FileObject {
-                    id,
-                    bytes: size_in_bytes as u64,
-                    created_at,
-                    filename,
-                    object: "file".to_string(),
-                    purpose: "assistants".to_string(),
-                });
+                    collection = Some(value.trim().to_string());
+                }
+                name => {
+                    let mut value = String::new();
+                    if let Err(e) = field.data.read_to_string(&mut value) {
+                        return error::bad_request(format!(
+                            "Failed to read the `{}` field. {}",
+                            name, e
+                        ));
+                    }
 
-                break;
+                    tags.insert(name.to_string(), value);
+                }
             }
         }
 
-        match file_object {
-            Some(fo) => fo,
+        let (filename, buffer) = match upload {
+            Some(upload) => upload,
             None => {
                 return error::internal_server_error(
                     "Failed to upload the target file. Not found the target file.",
                 )
             }
+        };
+
+        let collection_name = match target_collection(collection.as_deref()) {
+            Ok(collection_name) => collection_name,
+            Err(e) => return error::bad_request(e.to_string()),
+        };
+
+        // the documents of an archive are checked one by one once unpacked
+        let checksum = registry::checksum(&buffer);
+        if !ingest::archive::is_archive(&filename) {
+            let record = match registry::find_embedded(&checksum, &collection_name) {
+                Ok(record) => record,
+                Err(e) => return error::internal_server_error(e.to_string()),
+            };
+
+            if let Some(record) = record {
+                println!(
+                    "    * {} is identical to {}/{}",
+                    &filename, &record.id, &record.filename
+                );
+
+                match on_duplicate {
+                    OnDuplicate::Skip => {
+                        println!("[+] Upload skipped.\n");
+
+                        let embedding_response = EmbeddingsResponse {
+                            object: "list".to_string(),
+                            data: vec![],
+                            model: record.embedding_model.clone().unwrap_or_default(),
+                            usage: Usage {
+                                prompt_tokens: 0,
+                                completion_tokens: 0,
+                                total_tokens: 0,
+                            },
+                        };
+
+                        return json_response(&RagResponse {
+                            embeddings: embedding_response,
+                            duplicate: Some(DuplicateReport::new(
+                                DuplicateStatus::Skipped,
+                                &record,
+                            )),
+                        });
+                    }
+                    // the archived document is removed once the upload is indexed
+                    OnDuplicate::Replace => replaced = Some(record),
+                }
+            }
+        }
+
+        // create a unique file id
+        let id = format!("file_{}", uuid::Uuid::new_v4());
+
+        // save the file
+        let path = Path::new("archives");
+        if !path.exists() {
+            if let Err(e) = fs::create_dir(path) {
+                return error::internal_server_error(format!(
+                    "Failed to create the archive directory {}. {}",
+                    path.display(),
+                    e
+                ));
+            }
+        }
+        let file_path = path.join(&id);
+        if !file_path.exists() {
+            if let Err(e) = fs::create_dir(&file_path) {
+                return error::internal_server_error(format!(
+                    "Failed to create the archive directory {}. {}",
+                    file_path.display(),
+                    e
+                ));
+            }
+        }
+        let mut file = match File::create(file_path.join(&filename)) {
+            Ok(file) => file,
+            Err(e) => {
+                return error::internal_server_error(format!(
+                    "Failed to create archive document {}. {}",
+                    &filename, e
+                ));
+            }
+        };
+        if let Err(e) = file.write_all(&buffer[..]) {
+            return error::internal_server_error(format!(
+                "Failed to write archive document {}. {}",
+                &filename, e
+            ));
+        }
+
+        let created_at = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            Ok(n) => n.as_secs(),
+            Err(_) => return error::internal_server_error("Failed to get the current time."),
+        };
+
+        // create a file object
+        let file_object = FileObject {
+            id,
+            bytes: buffer.len() as u64,
+            created_at,
+            filename,
+            object: "file".to_string(),
+            purpose: "assistants".to_string(),
+        };
+
+        // the documents of an archive are recorded once unpacked
+        if !ingest::archive::is_archive(&file_object.filename) {
+            let record = registry::FileRecord {
+                tags: tags.clone(),
+                ..registry::FileRecord::new(&file_object, checksum)
+            };
+            if let Err(e) = registry::add(record) {
+                return error::internal_server_error(e.to_string());
+            }
         }
+
+        (file_object, collection_name)
     } else if req.method() == Method::GET {
         return error::internal_server_error("Not implemented for listing files.");
     } else {
         return error::internal_server_error("Invalid HTTP Method.");
     };
 
+    // index the documents of an uploaded archive one by one
+    if ingest::archive::is_archive(&file_object.filename) {
+        return archive_to_embeddings(
+            file_object,
+            &collection_name,
+            chunk_capacity,
+            on_duplicate,
+            tags,
+        )
+        .await;
+    }
+
     // chunk the text
     let chunks = {
         // check if the archives directory exists
@@ -895,96 +2055,508 @@ pub(crate) async fn doc_to_embeddings(
             return error::internal_server_error(message);
         }
 
-        // get the extension of the archived file
-        let extension = match file_path.extension().and_then(std::ffi::OsStr::to_str) {
-            Some(extension) => extension,
-            None => {
-                return error::internal_server_error(format!(
-                    "Failed to get the extension of the archived `{}`.",
-                    &file_object.filename
-                ));
-            }
+        let mut chunks = match ingest::chunk_file(&file_path, chunk_capacity) {
+            Ok(chunks) => chunks,
+            Err(e) => return error::internal_server_error(e.to_string()),
         };
+        if chunks.is_empty() {
+            return error::internal_server_error(format!(
+                "No text found in `{}`.",
+                &file_object.filename
+            ));
+        }
 
-        // open the file
-        let mut file = match File::open(&file_path) {
-            Ok(file) => file,
-            Err(e) => {
-                return error::internal_server_error(format!(
-                    "Failed to open `{}`. {}",
-                    &file_object.filename, e
-                ));
-            }
-        };
+        // record the source document of each chunk
+        ingest::set_document(
+            &mut chunks,
+            &document_meta(
+                &file_object.id,
+                &file_object.filename,
+                file_object.created_at,
+                &tags,
+            ),
+        );
+
+        chunks
+    };
+
+    // compute embeddings for chunks
+    let embedding_response = match embed_chunks(&chunks, &collection_name).await {
+        Ok(embedding_response) => embedding_response,
+        Err(e) => return error::internal_server_error(e.to_string()),
+    };
+    if let Err(e) = registry::set_embedded(
+        &file_object.id,
+        &file_object.filename,
+        chunks.len(),
+        &embedding_response.model,
+        &collection_name,
+    ) {
+        return error::internal_server_error(e.to_string());
+    }
 
-        // read the file
-        let mut contents = String::new();
-        if let Err(e) = file.read_to_string(&mut contents) {
+    let mut duplicate = None;
+    if let Some(record) = replaced {
+        if let Err(e) = remove_document(&record).await {
             return error::internal_server_error(format!(
-                "Failed to read `{}`. {}",
-                &file_object.filename, e
+                "{} is indexed as {}, but the archived document {}/{} it replaces failed to be removed. {}",
+                &file_object.filename, &file_object.id, &record.id, &record.filename, e
             ));
         }
+        duplicate = Some(DuplicateReport::new(DuplicateStatus::Replaced, &record));
+    }
 
-        match llama_core::rag::chunk_text(&contents, extension, chunk_capacity) {
-            Ok(chunks) => chunks,
-            Err(e) => return error::internal_server_error(e.to_string()),
+    // serialize embedding response
+    match serde_json::to_string(&RagResponse {
+        embeddings: embedding_response,
+        duplicate,
+    }) {
+        Ok(s) => {
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .body(Body::from(s));
+            match result {
+                Ok(response) => Ok(response),
+                Err(e) => error::internal_server_error(e.to_string()),
+            }
+        }
+        Err(e) => {
+            error::internal_server_error(format!("Fail to serialize embedding object. {}", e))
+        }
+    }
+}
+
+/// How an upload identical to an archived document is handled. Set by the `on_duplicate` field of `/v1/create/rag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnDuplicate {
+    /// Keep the archived document and drop the upload
+    Skip,
+    /// Index the upload, then remove the archived document and its points
+    Replace,
+}
+
+/// What happened to an upload identical to an archived document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DuplicateStatus {
+    Skipped,
+    Replaced,
+}
+
+/// Report of an upload identical to an archived document.
+#[derive(Debug, Serialize)]
+struct DuplicateReport {
+    status: DuplicateStatus,
+    /// Id of the archived document
+    id: String,
+    /// Name of the archived document
+    filename: String,
+}
+impl DuplicateReport {
+    fn new(status: DuplicateStatus, record: &registry::FileRecord) -> Self {
+        Self {
+            status,
+            id: record.id.clone(),
+            filename: record.filename.clone(),
+        }
+    }
+}
+
+/// Response of `/v1/create/rag` for a single document.
+#[derive(Debug, Serialize)]
+struct RagResponse {
+    #[serde(flatten)]
+    embeddings: EmbeddingsResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate: Option<DuplicateReport>,
+}
+
+/// Remove an archived document along with the points of its chunks.
+async fn remove_document(record: &registry::FileRecord) -> Result<(), ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+
+    println!("    * Removing {}/{}", &record.id, &record.filename);
+
+    let collection_name = record
+        .collection
+        .as_ref()
+        .unwrap_or(&server_info.qdrant_config.collection_name);
+    let ids = vector_store::document_points(collection_name, &record.id, &record.filename)
+        .await?
+        .into_iter()
+        .map(|point| point.id)
+        .collect();
+    vector_store::delete_points(collection_name, ids).await?;
+
+    let archive_path = Path::new("archives").join(&record.id);
+    match fs::remove_file(archive_path.join(&record.filename)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(ServerError::Operation(format!(
+                "Failed to remove {}. {}",
+                &record.filename, e
+            )))
         }
+        _ => {}
+    }
+    registry::remove(&record.id, Some(&record.filename))?;
+
+    // drop the archive directory along with its last document
+    if registry::list(Some(&record.id))?.is_empty() {
+        let _ = fs::remove_dir_all(&archive_path);
+    }
+
+    Ok(())
+}
+
+/// Compute the embeddings of the chunks and persist them in the given collection along with the metadata of the chunks.
+pub(crate) async fn embed_chunks(
+    chunks: &[DocChunk],
+    collection_name: &str,
+) -> Result<EmbeddingsResponse, ServerError> {
+    print_log_begin_separator("RAG (Embeddings for chunks)", Some("*"), None);
+
+    let embedding_response = compute_embeddings(chunks).await?;
+
+    // persist the embeddings along with the metadata of the chunks
+    vector_store::persist_chunks(collection_name, chunks, &embedding_response.data).await?;
+
+    print_log_end_separator(Some("*"), None);
+
+    Ok(embedding_response)
+}
+
+/// Compute the embeddings of the chunks with the first embedding model.
+async fn compute_embeddings(chunks: &[DocChunk]) -> Result<EmbeddingsResponse, ServerError> {
+    // create an embedding request
+    let embedding_request = EmbeddingRequest {
+        model: embedding_model_name()?,
+        input: chunks.iter().map(|chunk| chunk.text.clone()).collect(),
+        encoding_format: None,
+        user: None,
     };
 
-    // compute embeddings for chunks
-    let embedding_response = {
-        print_log_begin_separator("RAG (Embeddings for chunks)", Some("*"), None);
+    println!("[+] Computing embeddings for document chunks...");
+
+    llama_core::embeddings::embeddings(&embedding_request)
+        .await
+        .map_err(|e| ServerError::Operation(e.to_string()))
+}
+
+fn embedding_model_name() -> Result<String, ServerError> {
+    llama_core::utils::embedding_model_names()
+        .map_err(|e| ServerError::Operation(e.to_string()))?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::Operation(
+            "No embedding model is loaded.".to_string(),
+        ))
+}
+
+/// Chunk and embed the file at `file_path` as the given document into the given collection. Returns the number of chunks.
+pub(crate) async fn index_file(
+    file_path: impl AsRef<Path>,
+    document: &ChunkMeta,
+    collection_name: &str,
+    chunk_capacity: usize,
+) -> Result<usize, ServerError> {
+    let id = document.file_id.clone().unwrap_or_default();
+    let filename = document.filename.clone().unwrap_or_default();
+
+    let mut chunks = ingest::chunk_file(file_path, chunk_capacity)?;
+    if chunks.is_empty() {
+        return Err(ServerError::Operation(format!(
+            "No text found in `{}`.",
+            filename
+        )));
+    }
+
+    // record the source document of each chunk
+    ingest::set_document(&mut chunks, document);
+
+    let embedding_response = embed_chunks(&chunks, collection_name).await?;
+    registry::set_embedded(
+        &id,
+        &filename,
+        chunks.len(),
+        &embedding_response.model,
+        collection_name,
+    )?;
+
+    Ok(chunks.len())
+}
+
+/// Name of the collection the chunks of an upload go to: the requested one if it may be targeted, otherwise the default collection.
+fn target_collection(requested: Option<&str>) -> Result<String, ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+
+    match requested {
+        Some(name) => {
+            server_info.qdrant_config.check_collection(name)?;
+            Ok(name.to_string())
+        }
+        None => Ok(server_info.qdrant_config.collection_name.clone()),
+    }
+}
+
+/// Metadata of an archived document, recorded in the metadata of each of its chunks.
+pub(crate) fn document_meta(
+    id: &str,
+    filename: &str,
+    created_at: u64,
+    tags: &BTreeMap<String, String>,
+) -> ChunkMeta {
+    ChunkMeta {
+        file_id: Some(id.to_string()),
+        filename: Some(filename.to_string()),
+        created_at: Some(created_at),
+        tags: tags.clone(),
+        ..Default::default()
+    }
+}
+
+/// Result of indexing a document unpacked from an archive or downloaded from a url.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FileStatus {
+    Indexed,
+    Skipped,
+    Failed,
+}
+
+/// Report of a document unpacked from an archive.
+#[derive(Debug, Serialize)]
+struct FileReport {
+    /// Path of the document in the archive
+    filename: String,
+    bytes: u64,
+    status: FileStatus,
+    /// Number of chunks embedded
+    chunks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Set if the document is identical to an archived document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate: Option<DuplicateReport>,
+}
+
+/// Report of the documents indexed from an uploaded archive.
+#[derive(Debug, Serialize)]
+struct ArchiveReport {
+    /// Id of the archive directory the documents are unpacked to
+    id: String,
+    object: String,
+    /// Name of the uploaded archive
+    filename: String,
+    created_at: u64,
+    indexed: usize,
+    skipped: usize,
+    failed: usize,
+    files: Vec<FileReport>,
+}
+
+/// Unpack an uploaded archive under its archive directory, then chunk and embed every supported document in it into the given collection.
+async fn archive_to_embeddings(
+    file_object: FileObject,
+    collection_name: &str,
+    chunk_capacity: usize,
+    on_duplicate: OnDuplicate,
+    tags: BTreeMap<String, String>,
+) -> Result<Response<Body>, hyper::Error> {
+    let archive_path = Path::new("archives").join(&file_object.id);
+    let archive_file = archive_path.join(&file_object.filename);
+
+    println!("[+] Unpacking {} ...", &file_object.filename);
+
+    let bytes = match fs::read(&archive_file) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return error::internal_server_error(format!(
+                "Failed to read {}. {}",
+                &file_object.filename, e
+            ))
+        }
+    };
+    let entries = match ingest::archive::unpack(&file_object.filename, &bytes, &archive_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            // keep the uploaded archive, without the files unpacked so far
+            if let Ok(dir) = fs::read_dir(&archive_path) {
+                for path in dir.flatten().map(|entry| entry.path()) {
+                    if path == archive_file {
+                        continue;
+                    }
+                    let _ = match path.is_dir() {
+                        true => fs::remove_dir_all(&path),
+                        false => fs::remove_file(&path),
+                    };
+                }
+            }
+            return error::internal_server_error(e.to_string());
+        }
+    };
+    // the unpacked documents take the place of the archive, unless one of them overwrote it
+    if !entries
+        .iter()
+        .any(|entry| archive_path.join(&entry.filename) == archive_file)
+    {
+        if let Err(e) = fs::remove_file(&archive_file) {
+            return error::internal_server_error(format!(
+                "Failed to remove {}. {}",
+                &file_object.filename, e
+            ));
+        }
+    }
+
+    println!("    * Number of files: {}", entries.len());
 
-        // get the name of embedding model
-        let model = match llama_core::utils::embedding_model_names() {
-            Ok(model_names) => model_names[0].clone(),
+    let mut files = vec![];
+    for entry in entries {
+        let bytes = match fs::read(archive_path.join(&entry.filename)) {
+            Ok(bytes) => bytes,
             Err(e) => {
-                return error::internal_server_error(e.to_string());
+                return error::internal_server_error(format!(
+                    "Failed to read {}. {}",
+                    &entry.filename, e
+                ))
             }
         };
-        // create an embedding request
-        let embedding_request = EmbeddingRequest {
-            model,
-            input: chunks,
-            encoding_format: None,
-            user: None,
+        let checksum = registry::checksum(&bytes);
+
+        let mut report = FileReport {
+            filename: entry.filename,
+            bytes: entry.bytes,
+            status: FileStatus::Skipped,
+            chunks: 0,
+            error: None,
+            duplicate: None,
         };
+        let mut replaced = None;
 
-        // let qdrant_config = match QDRANT_CONFIG.get() {
-        //     Some(qdrant_config) => qdrant_config,
-        //     None => {
-        //         return error::internal_server_error("The Qdrant config is not set.");
-        //     }
-        // };
+        if ingest::is_supported(&report.filename) {
+            let record = match registry::find_embedded(&checksum, collection_name) {
+                Ok(record) => record,
+                Err(e) => return error::internal_server_error(e.to_string()),
+            };
 
-        let server_info = match SERVER_INFO.get() {
-            Some(server_info) => server_info,
-            None => {
-                return error::internal_server_error("The server info is not set.");
+            if let Some(record) = record {
+                println!(
+                    "\n[+] {} is identical to {}/{}",
+                    &report.filename, &record.id, &record.filename
+                );
+
+                match on_duplicate {
+                    OnDuplicate::Skip => {
+                        let _ = fs::remove_file(archive_path.join(&report.filename));
+                        report.duplicate =
+                            Some(DuplicateReport::new(DuplicateStatus::Skipped, &record));
+                        files.push(report);
+                        continue;
+                    }
+                    // the archived document is removed once the document is indexed
+                    OnDuplicate::Replace => replaced = Some(record),
+                }
             }
+        }
+
+        let record = registry::FileRecord {
+            tags: tags.clone(),
+            ..registry::FileRecord::new(
+                &FileObject {
+                    id: file_object.id.clone(),
+                    bytes: report.bytes,
+                    created_at: file_object.created_at,
+                    filename: report.filename.clone(),
+                    object: "file".to_string(),
+                    purpose: file_object.purpose.clone(),
+                },
+                checksum,
+            )
         };
+        if let Err(e) = registry::add(record) {
+            return error::internal_server_error(e.to_string());
+        }
 
-        // create rag embedding request
-        let rag_embedding_request = RagEmbeddingRequest::from_embedding_request(
-            embedding_request,
-            server_info.qdrant_config.url.clone(),
-            server_info.qdrant_config.collection_name.clone(),
-        );
+        if !ingest::is_supported(&report.filename) {
+            report.error = Some("Unsupported file type.".to_string());
+            files.push(report);
+            continue;
+        }
 
-        let embedding_response =
-            match llama_core::rag::rag_doc_chunks_to_embeddings(&rag_embedding_request).await {
-                Ok(embedding_response) => embedding_response,
-                Err(e) => return error::internal_server_error(e.to_string()),
-            };
+        println!("\n[+] Indexing {} ...", &report.filename);
+
+        let file_path = archive_path.join(&report.filename);
+        let document = document_meta(
+            &file_object.id,
+            &report.filename,
+            file_object.created_at,
+            &tags,
+        );
+        match index_file(file_path, &document, collection_name, chunk_capacity).await {
+            Ok(chunks) => {
+                report.status = FileStatus::Indexed;
+                report.chunks = chunks;
+
+                if let Some(record) = replaced {
+                    match remove_document(&record).await {
+                        Ok(_) => {
+                            report.duplicate =
+                                Some(DuplicateReport::new(DuplicateStatus::Replaced, &record))
+                        }
+                        Err(e) => {
+                            println!(
+                                "    * Failed to remove {}/{}. {}",
+                                &record.id, &record.filename, e
+                            );
+                            report.error = Some(format!(
+                                "The archived document {}/{} it replaces failed to be removed. {}",
+                                &record.id, &record.filename, e
+                            ));
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("    * Failed to index {}. {}", &report.filename, e);
+                report.status = FileStatus::Failed;
+                report.error = Some(e.to_string());
+            }
+        }
+        files.push(report);
+    }
 
-        print_log_begin_separator("RAG (Embeddings for chunks)", Some("*"), None);
+    // nothing is left of an archive whose documents are all skipped
+    if let Ok(records) = registry::list(Some(&file_object.id)) {
+        if records.is_empty() {
+            let _ = fs::remove_dir_all(&archive_path);
+        }
+    }
 
-        embedding_response
+    let count = |status: FileStatus| files.iter().filter(|file| file.status == status).count();
+    let archive_report = ArchiveReport {
+        id: file_object.id,
+        object: "archive".to_string(),
+        filename: file_object.filename,
+        created_at: file_object.created_at,
+        indexed: count(FileStatus::Indexed),
+        skipped: count(FileStatus::Skipped),
+        failed: count(FileStatus::Failed),
+        files,
     };
 
-    // serialize embedding response
-    match serde_json::to_string(&embedding_response) {
+    println!(
+        "\n[+] Archive indexed: {} indexed, {} skipped, {} failed.\n",
+        archive_report.indexed, archive_report.skipped, archive_report.failed
+    );
+
+    // serialize archive report
+    match serde_json::to_string(&archive_report) {
         Ok(s) => {
             // return response
             let result = Response::builder()
@@ -997,12 +2569,211 @@ pub(crate) async fn doc_to_embeddings(
                 Err(e) => error::internal_server_error(e.to_string()),
             }
         }
+        Err(e) => error::internal_server_error(format!("Fail to serialize archive report. {}", e)),
+    }
+}
+
+/// Request of `/v1/create/rag/url`.
+#[derive(Debug, Deserialize)]
+struct UrlsRequest {
+    /// Urls of the documents to download
+    urls: Vec<String>,
+    /// Key/value tags of the documents
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    /// Collection to persist the chunks in. Defaults to the default collection.
+    #[serde(default)]
+    collection: Option<String>,
+}
+
+/// Report of a document downloaded from a url.
+#[derive(Debug, Serialize)]
+struct UrlReport {
+    url: String,
+    /// Id of the archive directory the document is stored in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// Name of the archived document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    bytes: u64,
+    status: FileStatus,
+    /// Number of chunks embedded
+    chunks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Report of the documents indexed from urls.
+#[derive(Debug, Serialize)]
+struct UrlsReport {
+    object: String,
+    indexed: usize,
+    failed: usize,
+    data: Vec<UrlReport>,
+}
+
+/// Download the documents at the given urls into the archives directory, then chunk and embed them.
+pub(crate) async fn url_to_embeddings(
+    mut req: Request<Body>,
+    chunk_capacity: usize,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::POST {
+        return error::internal_server_error("Invalid HTTP Method.");
+    }
+
+    println!("\n[+] Running url handler ...");
+
+    // parse request
+    let body_bytes = to_bytes(req.body_mut()).await?;
+    let urls_request: UrlsRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(urls_request) => urls_request,
         Err(e) => {
-            error::internal_server_error(format!("Fail to serialize embedding object. {}", e))
+            return error::bad_request(format!("Fail to parse urls request: {msg}", msg = e));
+        }
+    };
+    if urls_request.urls.is_empty() {
+        return error::bad_request("No url in the request.");
+    }
+    let collection_name = match target_collection(urls_request.collection.as_deref()) {
+        Ok(collection_name) => collection_name,
+        Err(e) => return error::bad_request(e.to_string()),
+    };
+
+    let mut data = vec![];
+    for url in urls_request.urls.iter().cloned() {
+        println!("\n[+] Downloading {} ...", &url);
+
+        let mut report = UrlReport {
+            url,
+            id: None,
+            filename: None,
+            bytes: 0,
+            status: FileStatus::Failed,
+            chunks: 0,
+            error: None,
+        };
+
+        let result = match download(&report.url, &urls_request.tags).await {
+            Ok(file_object) => {
+                println!(
+                    "    * Saved to {}/{}",
+                    &file_object.id, &file_object.filename
+                );
+
+                report.id = Some(file_object.id.clone());
+                report.filename = Some(file_object.filename.clone());
+                report.bytes = file_object.bytes;
+
+                let file_path = Path::new("archives")
+                    .join(&file_object.id)
+                    .join(&file_object.filename);
+                let document = document_meta(
+                    &file_object.id,
+                    &file_object.filename,
+                    file_object.created_at,
+                    &urls_request.tags,
+                );
+                index_file(file_path, &document, &collection_name, chunk_capacity).await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(chunks) => {
+                report.status = FileStatus::Indexed;
+                report.chunks = chunks;
+            }
+            Err(e) => {
+                println!("    * Failed to index {}. {}", &report.url, e);
+                report.error = Some(e.to_string());
+            }
+        }
+        data.push(report);
+    }
+
+    let indexed = data
+        .iter()
+        .filter(|report| report.status == FileStatus::Indexed)
+        .count();
+    let urls_report = UrlsReport {
+        object: "list".to_string(),
+        indexed,
+        failed: data.len() - indexed,
+        data,
+    };
+
+    println!(
+        "\n[+] Urls indexed: {} indexed, {} failed.\n",
+        urls_report.indexed, urls_report.failed
+    );
+
+    // serialize urls report
+    match serde_json::to_string(&urls_report) {
+        Ok(s) => {
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .body(Body::from(s));
+            match result {
+                Ok(response) => Ok(response),
+                Err(e) => error::internal_server_error(e.to_string()),
+            }
         }
+        Err(e) => error::internal_server_error(format!("Fail to serialize urls report. {}", e)),
     }
 }
 
+/// Download the document at `url` and save it in a new archive directory.
+async fn download(url: &str, tags: &BTreeMap<String, String>) -> Result<FileObject, ServerError> {
+    let ingest::download::Download { filename, bytes } = ingest::download::fetch(
+        url,
+        ingest::download::MAX_DOWNLOAD_BYTES,
+        DOWNLOAD_ALLOWED_HOSTS
+            .get()
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+    )
+    .await?;
+
+    // create a unique file id
+    let id = format!("file_{}", uuid::Uuid::new_v4());
+
+    // save the file
+    let file_path = Path::new("archives").join(&id);
+    fs::create_dir_all(&file_path).map_err(|e| {
+        ServerError::Operation(format!("Failed to create archive directory {}. {}", &id, e))
+    })?;
+    fs::write(file_path.join(&filename), &bytes).map_err(|e| {
+        ServerError::Operation(format!(
+            "Failed to create archive document {}. {}",
+            &filename, e
+        ))
+    })?;
+
+    let created_at = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| ServerError::Operation("Failed to get the current time.".to_string()))?
+        .as_secs();
+
+    let file_object = FileObject {
+        id,
+        bytes: bytes.len() as u64,
+        created_at,
+        filename,
+        object: "file".to_string(),
+        purpose: "assistants".to_string(),
+    };
+    registry::add(registry::FileRecord {
+        tags: tags.clone(),
+        ..registry::FileRecord::new(&file_object, registry::checksum(&bytes))
+    })?;
+
+    Ok(file_object)
+}
+
 pub(crate) async fn server_info() -> Result<Response<Body>, hyper::Error> {
     // get the server info
     let server_info = match SERVER_INFO.get() {
@@ -1062,6 +2833,16 @@ pub(crate) async fn retrieve_handler(
         }
     };
 
+    let retrieve_options: RetrieveOptions = match serde_json::from_slice(&body_bytes) {
+        Ok(retrieve_options) => retrieve_options,
+        Err(e) => {
+            return error::bad_request(format!(
+                "Fail to parse the retrieval options: {msg}",
+                msg = e
+            ));
+        }
+    };
+
     let server_info = match SERVER_INFO.get() {
         Some(server_info) => server_info,
         None => {
@@ -1069,76 +2850,27 @@ pub(crate) async fn retrieve_handler(
         }
     };
 
-    println!("\n[+] Computing embeddings for user query ...");
-
-    // * compute embeddings for user query
-    let embedding_response = match chat_request.messages.is_empty() {
-        true => return error::bad_request("Messages should not be empty"),
-        false => {
-            let last_message = chat_request.messages.last().unwrap();
-            match last_message {
-                ChatCompletionRequestMessage::User(user_message) => {
-                    let query_text = match user_message.content() {
-                        ChatCompletionUserMessageContent::Text(text) => text,
-                        _ => {
-                            return error::bad_request(
-                                "The last message must be a text content user message",
-                            )
-                        }
-                    };
-
-                    println!("    * user query: {}\n", query_text);
-
-                    // get the available embedding models
-                    let embedding_model_names = match llama_core::utils::embedding_model_names() {
-                        Ok(model_names) => model_names,
-                        Err(e) => return error::internal_server_error(e.to_string()),
-                    };
-
-                    // create a embedding request
-                    let embedding_request = EmbeddingRequest {
-                        model: embedding_model_names[0].clone(),
-                        input: vec![query_text.clone()],
-                        encoding_format: None,
-                        user: chat_request.user.clone(),
-                    };
-
-                    if let Ok(request_str) = serde_json::to_string_pretty(&embedding_request) {
-                        println!("    * embedding request (json):\n\n{}", request_str);
-                    }
-
-                    let rag_embedding_request = RagEmbeddingRequest {
-                        embedding_request,
-                        qdrant_url: server_info.qdrant_config.url.clone(),
-                        qdrant_collection_name: server_info.qdrant_config.collection_name.clone(),
-                    };
+    // the retrieval settings of the server, overridden by the request
+    let retrieval =
+        match retrieve_options.resolve(&server_info.qdrant_config, chat_request.model.as_deref()) {
+            Ok(retrieval) => retrieval,
+            Err(e) => return error::bad_request(e.to_string()),
+        };
 
-                    // compute embeddings for query
-                    match llama_core::rag::rag_query_to_embeddings(&rag_embedding_request).await {
-                        Ok(embedding_response) => embedding_response,
-                        Err(e) => {
-                            return error::internal_server_error(e.to_string());
-                        }
-                    }
-                }
-                _ => return error::bad_request("The last message must be a user message"),
-            }
-        }
-    };
-    let query_embedding: Vec<f32> = match embedding_response.data.first() {
-        Some(embedding) => embedding.embedding.iter().map(|x| *x as f32).collect(),
-        None => return error::internal_server_error("No embeddings returned"),
+    let query_text = match last_user_query(&chat_request.messages) {
+        Ok(query_text) => query_text,
+        Err(e) => return error::bad_request(e.to_string()),
     };
 
-    println!("\n[+] Retrieving context ...");
+    println!("    * user query: {}", query_text);
 
-    // * retrieve context
-    match llama_core::rag::rag_retrieve_context(
-        query_embedding.as_slice(),
-        server_info.qdrant_config.url.to_string().as_str(),
-        server_info.qdrant_config.collection_name.as_str(),
-        server_info.qdrant_config.limit as usize,
-        Some(server_info.qdrant_config.score_threshold),
+    // the model of the request may only route to a collection, so the default chat model rewrites and reranks
+    match retrieve_context(
+        &query_text,
+        &chat_request.messages,
+        chat_request.user.clone(),
+        &retrieval,
+        None,
     )
     .await
     {
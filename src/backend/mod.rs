@@ -12,9 +12,13 @@ pub(crate) async fn handle_llama_request(
         "/v1/models" => ggml::models_handler().await,
         "/v1/embeddings" => ggml::rag_doc_chunks_to_embeddings2_handler(req).await,
         "/v1/files" => ggml::files_handler(req).await,
+        path if path.starts_with("/v1/files/") => ggml::file_handler(req, chunk_capacity).await,
         "/v1/chunks" => ggml::chunks_handler(req).await,
         "/v1/retrieve" => ggml::retrieve_handler(req).await,
         "/v1/create/rag" => ggml::doc_to_embeddings(req, chunk_capacity).await,
+        "/v1/create/rag/url" => ggml::url_to_embeddings(req, chunk_capacity).await,
+        "/v1/collections" => ggml::collections_handler(req).await,
+        path if path.starts_with("/v1/collections/") => ggml::collection_handler(req).await,
         "/v1/info" => ggml::server_info().await,
         _ => error::invalid_endpoint(req.uri().path()),
     }
@@ -0,0 +1,142 @@
+//! Named Qdrant collections, configured by the YAML file of `--collections-config`.
+//!
+//! A request picks a collection with its `rag_collection` field, or by its `model` name if the name is routed to a collection. Otherwise the default collection is used.
+
+use crate::{error::ServerError, QdrantConfig};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path};
+
+/// A named collection, i.e. a knowledge base.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CollectionConfig {
+    pub(crate) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    /// Model names routed to the collection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) models: Vec<String>,
+    /// Max number of retrieved result. Defaults to `--qdrant-limit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) limit: Option<u64>,
+    /// Minimal score threshold for the search result. Defaults to `--qdrant-score-threshold`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) score_threshold: Option<f32>,
+}
+
+/// Contents of the collections config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollectionsConfig {
+    /// Collection used by the requests that pick none. Defaults to `--qdrant-collection-name`.
+    #[serde(default)]
+    default: Option<String>,
+    collections: Vec<CollectionConfig>,
+}
+
+/// Load the collections config at `path`. Returns the name of the default collection, if set, and the collections.
+pub(crate) fn load(path: &Path) -> Result<(Option<String>, Vec<CollectionConfig>), ServerError> {
+    let yaml = fs::read_to_string(path).map_err(|e| {
+        ServerError::ArgumentError(format!(
+            "Failed to read the collections config {}. {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let config: CollectionsConfig = serde_yaml::from_str(&yaml).map_err(|e| {
+        ServerError::ArgumentError(format!(
+            "Failed to parse the collections config {}. {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let mut names = HashSet::new();
+    let mut models = HashSet::new();
+    for collection in config.collections.iter() {
+        if !is_valid_collection_name(&collection.name) {
+            return Err(ServerError::ArgumentError(format!(
+                "Invalid collection name: {}",
+                &collection.name
+            )));
+        }
+        if !names.insert(collection.name.as_str()) {
+            return Err(ServerError::ArgumentError(format!(
+                "The collection {} is configured twice.",
+                &collection.name
+            )));
+        }
+        if collection.limit == Some(0) {
+            return Err(ServerError::ArgumentError(format!(
+                "The limit of the collection {} must be no less than 1.",
+                &collection.name
+            )));
+        }
+        for model in collection.models.iter() {
+            if !models.insert(model.as_str()) {
+                return Err(ServerError::ArgumentError(format!(
+                    "The model {} is routed to more than one collection.",
+                    model
+                )));
+            }
+        }
+    }
+    if let Some(default) = &config.default {
+        if !names.contains(default.as_str()) {
+            return Err(ServerError::ArgumentError(format!(
+                "The default collection {} is not configured.",
+                default
+            )));
+        }
+    }
+
+    Ok((config.default, config.collections))
+}
+
+/// Collection names are used in the paths of the Qdrant REST API.
+pub(crate) fn is_valid_collection_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl QdrantConfig {
+    /// Find the configured collection of the given name.
+    pub(crate) fn collection(&self, name: &str) -> Option<&CollectionConfig> {
+        self.collections
+            .iter()
+            .find(|collection| collection.name == name)
+    }
+
+    /// Find the collection the given model name is routed to.
+    pub(crate) fn collection_of_model(&self, model: &str) -> Option<&CollectionConfig> {
+        self.collections
+            .iter()
+            .find(|collection| collection.models.iter().any(|m| m == model))
+    }
+
+    /// Check that a request may target the given collection. Without a collections config, any collection may be targeted.
+    pub(crate) fn check_collection(&self, name: &str) -> Result<(), ServerError> {
+        if !is_valid_collection_name(name) {
+            return Err(ServerError::Operation(format!(
+                "Invalid collection name: {}",
+                name
+            )));
+        }
+        if !self.collections.is_empty() && self.collection(name).is_none() {
+            return Err(ServerError::Operation(format!(
+                "The collection {} is not configured. Available collections: {}",
+                name,
+                self.collections
+                    .iter()
+                    .map(|collection| collection.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )));
+        }
+
+        Ok(())
+    }
+}
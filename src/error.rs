@@ -28,6 +28,16 @@ pub(crate) fn bad_request(msg: impl AsRef<str>) -> Result<Response<Body>, hyper:
     Ok(response)
 }
 
+pub(crate) fn not_found(msg: impl AsRef<str>) -> Result<Response<Body>, hyper::Error> {
+    let err_msg = match msg.as_ref().is_empty() {
+        true => "404 Not Found".to_string(),
+        false => format!("404 Not Found: {}", msg.as_ref()),
+    };
+    let mut response = Response::new(Body::from(err_msg));
+    *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+    Ok(response)
+}
+
 pub(crate) fn invalid_endpoint(msg: impl AsRef<str>) -> Result<Response<Body>, hyper::Error> {
     let err_msg = match msg.as_ref().is_empty() {
         true => "404 The requested service endpoint is not found".to_string(),
@@ -0,0 +1,180 @@
+//! Unpack uploaded `zip` and `tar.gz` archives of documents.
+
+use crate::error::ServerError;
+use std::{
+    fs::{self, File},
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+
+/// Suffixes of the archives that can be unpacked.
+pub(crate) const ARCHIVE_EXTENSIONS: [&str; 3] = [".zip", ".tar.gz", ".tgz"];
+/// Max number of entries of an archive, including directories and skipped files
+pub(crate) const MAX_ENTRIES: usize = 10_000;
+/// Max total size (in bytes) of the files unpacked from an archive
+pub(crate) const MAX_UNPACKED_BYTES: u64 = 1 << 30;
+
+/// A file unpacked from an archive.
+#[derive(Debug, Clone)]
+pub(crate) struct ArchiveEntry {
+    /// Path of the file relative to the directory the archive is unpacked to
+    pub(crate) filename: String,
+    pub(crate) bytes: u64,
+}
+
+/// Check if the given file is an archive by its extension.
+pub(crate) fn is_archive(filename: impl AsRef<str>) -> bool {
+    let filename = filename.as_ref().to_lowercase();
+    ARCHIVE_EXTENSIONS.iter().any(|ext| filename.ends_with(ext))
+}
+
+/// Unpack the files of the archive named `name` into `dir`, keeping their relative paths.
+///
+/// Directories, links, hidden files and entries escaping `dir` are skipped. Archives with more than [`MAX_ENTRIES`] entries, or unpacking to more than [`MAX_UNPACKED_BYTES`] bytes, are rejected; the files unpacked so far are left in `dir`.
+pub(crate) fn unpack(
+    name: &str,
+    bytes: &[u8],
+    dir: impl AsRef<Path>,
+) -> Result<Vec<ArchiveEntry>, ServerError> {
+    match name.to_lowercase().ends_with(".zip") {
+        true => unpack_zip(bytes, dir.as_ref()),
+        false => unpack_tar_gz(bytes, dir.as_ref()),
+    }
+}
+
+fn unpack_zip(bytes: &[u8], dir: &Path) -> Result<Vec<ArchiveEntry>, ServerError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| ServerError::Operation(format!("Failed to open the zip archive. {}", e)))?;
+    if archive.len() > MAX_ENTRIES {
+        return Err(too_many_entries());
+    }
+
+    let mut entries = vec![];
+    let mut unpacked = 0;
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx).map_err(|e| {
+            ServerError::Operation(format!("Failed to read the zip archive. {}", e))
+        })?;
+        if !file.is_file() {
+            continue;
+        }
+
+        let path = match file.enclosed_name().and_then(sanitize) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let entry = write_entry(&mut file, dir, &path, MAX_UNPACKED_BYTES - unpacked)?;
+        unpacked += entry.bytes;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+fn unpack_tar_gz(bytes: &[u8], dir: &Path) -> Result<Vec<ArchiveEntry>, ServerError> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(Cursor::new(bytes)));
+
+    let files = archive
+        .entries()
+        .map_err(|e| ServerError::Operation(format!("Failed to open the tar.gz archive. {}", e)))?;
+
+    let mut entries = vec![];
+    let mut unpacked = 0;
+    for (idx, file) in files.enumerate() {
+        if idx >= MAX_ENTRIES {
+            return Err(too_many_entries());
+        }
+        let mut file = file.map_err(|e| {
+            ServerError::Operation(format!("Failed to read the tar.gz archive. {}", e))
+        })?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = match file.path().ok().and_then(|path| sanitize(&path)) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let entry = write_entry(&mut file, dir, &path, MAX_UNPACKED_BYTES - unpacked)?;
+        unpacked += entry.bytes;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Keep the normal components of the path only. Paths with `..`, roots or hidden components are rejected.
+fn sanitize(path: &Path) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                let part = part.to_str()?;
+                if part.starts_with('.') || part == "__MACOSX" {
+                    return None;
+                }
+                sanitized.push(part);
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    match sanitized.as_os_str().is_empty() {
+        true => None,
+        false => Some(sanitized),
+    }
+}
+
+fn too_many_entries() -> ServerError {
+    ServerError::Operation(format!(
+        "The archive has more than {} entries.",
+        MAX_ENTRIES
+    ))
+}
+
+/// Write the entry to `path` under `dir`. Fails if the entry is larger than `max_bytes`.
+fn write_entry(
+    reader: &mut impl Read,
+    dir: &Path,
+    path: &Path,
+    max_bytes: u64,
+) -> Result<ArchiveEntry, ServerError> {
+    let filename = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let file_path = dir.join(path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to create the directory of `{}`. {}",
+                &filename, e
+            ))
+        })?;
+    }
+
+    let mut file = File::create(&file_path).map_err(|e| {
+        ServerError::Operation(format!(
+            "Failed to create archive document {}. {}",
+            &filename, e
+        ))
+    })?;
+    // the sizes in the headers may lie, so the limit holds on the bytes actually unpacked
+    let bytes = io::copy(&mut reader.take(max_bytes + 1), &mut file)
+        .map_err(|e| ServerError::Operation(format!("Failed to unpack `{}`. {}", &filename, e)))?;
+    if bytes > max_bytes {
+        drop(file);
+        let _ = fs::remove_file(&file_path);
+        return Err(ServerError::Operation(format!(
+            "The archive unpacks to more than {} bytes.",
+            MAX_UNPACKED_BYTES
+        )));
+    }
+
+    Ok(ArchiveEntry { filename, bytes })
+}
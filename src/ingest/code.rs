@@ -0,0 +1,1234 @@
+use super::DocChunk;
+use crate::error::ServerError;
+use tiktoken_rs::CoreBPE;
+
+/// How the blocks of a language are delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    /// Blocks are enclosed in `{` and `}`
+    Braces,
+    /// Blocks are indented, e.g. Python
+    Indentation,
+}
+
+/// Lexical traits of a programming language.
+pub(crate) struct Language {
+    pub(crate) name: &'static str,
+    extensions: &'static [&'static str],
+    syntax: Syntax,
+    /// Marker of line comments
+    line_comment: &'static str,
+    /// Quotes of string literals. Other quotes are taken as character literals or lifetimes.
+    quotes: &'static [char],
+    /// Quotes of raw string literals, without escape sequences, e.g. the backquotes of go
+    raw_quotes: &'static [char],
+    /// Prefix of raw string literals of `"`, e.g. `r` of rust, which may be followed by `#`s closing the literal along with the quote
+    raw_prefix: Option<&'static str>,
+    /// Separator of the path of a nested symbol, e.g. `Foo::bar`
+    separator: &'static str,
+}
+
+/// Programming languages of the source files that can be chunked.
+pub(crate) const LANGUAGES: [Language; 11] = [
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: "::",
+        raw_quotes: &[],
+        raw_prefix: Some("r"),
+    },
+    Language {
+        name: "python",
+        extensions: &["py", "pyi"],
+        syntax: Syntax::Indentation,
+        line_comment: "#",
+        quotes: &['"', '\''],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "javascript",
+        extensions: &["js", "jsx", "mjs", "cjs"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"', '\'', '`'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "typescript",
+        extensions: &["ts", "tsx", "mts", "cts"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"', '\'', '`'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "go",
+        extensions: &["go"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: ".",
+        raw_quotes: &['`'],
+        raw_prefix: None,
+    },
+    Language {
+        name: "java",
+        extensions: &["java"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "kotlin",
+        extensions: &["kt", "kts"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "csharp",
+        extensions: &["cs"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: Some("@"),
+    },
+    Language {
+        name: "c",
+        extensions: &["c", "h"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: "::",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "c++",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: "::",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+    Language {
+        name: "swift",
+        extensions: &["swift"],
+        syntax: Syntax::Braces,
+        line_comment: "//",
+        quotes: &['"'],
+        separator: ".",
+        raw_quotes: &[],
+        raw_prefix: None,
+    },
+];
+
+/// Keywords introducing the name of a declaration.
+const DECLARATION_KEYWORDS: [&str; 20] = [
+    "fn",
+    "fun",
+    "func",
+    "function",
+    "def",
+    "class",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "interface",
+    "protocol",
+    "extension",
+    "namespace",
+    "mod",
+    "module",
+    "object",
+    "record",
+    "type",
+    "macro_rules",
+];
+
+/// Keywords of declarations whose members are chunked apart when the declaration is too large.
+const CONTAINER_KEYWORDS: [&str; 13] = [
+    "class",
+    "struct",
+    "enum",
+    "trait",
+    "interface",
+    "protocol",
+    "extension",
+    "namespace",
+    "mod",
+    "module",
+    "object",
+    "record",
+    "type",
+];
+
+/// Keywords introducing the name of a variable or constant.
+const VARIABLE_KEYWORDS: [&str; 5] = ["const", "static", "let", "var", "val"];
+
+/// Keywords of statements that import other modules.
+const IMPORT_KEYWORDS: [&str; 7] = [
+    "use", "import", "package", "from", "using", "include", "require",
+];
+
+/// Keywords that never name a declaration.
+const RESERVED_KEYWORDS: [&str; 22] = [
+    "if", "else", "elif", "for", "while", "loop", "do", "switch", "match", "case", "return", "try",
+    "catch", "except", "finally", "with", "function", "async", "await", "new", "typeof", "sizeof",
+];
+
+/// Modifiers that may precede a declaration.
+const MODIFIERS: [&str; 16] = [
+    "pub",
+    "crate",
+    "super",
+    "self",
+    "in",
+    "export",
+    "default",
+    "public",
+    "private",
+    "protected",
+    "internal",
+    "abstract",
+    "final",
+    "async",
+    "unsafe",
+    "override",
+];
+
+/// Find the language of a source file by its extension.
+pub(crate) fn language(ext: &str) -> Option<&'static Language> {
+    LANGUAGES
+        .iter()
+        .find(|language| language.extensions.contains(&ext))
+}
+
+/// Extensions of the source files that can be chunked.
+pub(crate) fn extensions() -> impl Iterator<Item = &'static str> {
+    LANGUAGES
+        .iter()
+        .flat_map(|language| language.extensions.iter().copied())
+}
+
+/// Split a source file at the boundaries of its functions, classes and implementation blocks.
+///
+/// Every chunk holds whole declarations and records the name of its symbol and its line range. Declarations larger than `chunk_capacity` tokens are split at the boundaries of their members, and then by lines.
+pub(crate) fn chunk_source(
+    source: &str,
+    language: &'static Language,
+    tokenizer: CoreBPE,
+    chunk_capacity: usize,
+) -> Result<Vec<DocChunk>, ServerError> {
+    println!(
+        "[+] Chunking the {} source file by declarations ...",
+        language.name
+    );
+
+    let splitter = Splitter {
+        lines: scan(source, language),
+        language,
+        tokenizer,
+        chunk_capacity,
+    };
+
+    let mut pieces = vec![];
+    for item in splitter.items(0, splitter.lines.len(), 0) {
+        splitter.split(&item, None, &mut pieces);
+    }
+    let pieces = splitter.merge(pieces);
+
+    let chunks = pieces
+        .into_iter()
+        .filter_map(|piece| splitter.to_chunk(piece))
+        .collect::<Vec<DocChunk>>();
+
+    println!("    * Number of chunks: {}", chunks.len());
+
+    Ok(chunks)
+}
+
+/// A line of source code with its lexical context.
+#[derive(Debug)]
+struct Line<'a> {
+    text: &'a str,
+    /// Bracket depth at the start of the line
+    depth: usize,
+    /// Bracket depth at the end of the line
+    depth_after: usize,
+    /// Whether the line starts inside a string literal
+    in_string: bool,
+    /// Last character of code in the line, comments excluded
+    last: Option<char>,
+    /// Width of the leading whitespace
+    indent: usize,
+}
+impl Line<'_> {
+    fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    /// Whether the line continues the statement of the previous line.
+    fn is_continued(&self) -> bool {
+        self.depth > 0 || self.in_string
+    }
+
+    /// Whether the line is a comment, an attribute or a decorator of the following declaration.
+    fn is_decoration(&self, language: &Language) -> bool {
+        let text = self.text.trim_start();
+        text.starts_with(language.line_comment)
+            || text.starts_with('@')
+            || (language.syntax == Syntax::Braces
+                && (text.starts_with("/*")
+                    || text.starts_with('*')
+                    || text.starts_with("#[")
+                    || text.starts_with("#!")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    BlockComment,
+    String(char),
+    TripleQuoted(char),
+    /// A raw string closed by the quote followed by the number of `#`s
+    RawString(char, usize),
+}
+
+/// Track brackets, strings and comments line by line.
+fn scan<'a>(source: &'a str, language: &Language) -> Vec<Line<'a>> {
+    let mut lines = vec![];
+    let mut state = State::Code;
+    let mut depth = 0usize;
+    for text in source.lines() {
+        let chars = text.chars().collect::<Vec<char>>();
+        let in_string = matches!(
+            state,
+            State::String(_) | State::TripleQuoted(_) | State::RawString(..)
+        );
+        let start_depth = depth;
+        let mut last = None;
+
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            match state {
+                State::BlockComment => {
+                    if c == '*' && next == Some('/') {
+                        state = State::Code;
+                        i += 1;
+                    }
+                }
+                State::String(quote) => {
+                    if c == '\\' {
+                        i += 1;
+                    } else if c == quote {
+                        state = State::Code;
+                    }
+                }
+                State::RawString(quote, hashes) => {
+                    if c == quote && (1..=hashes).all(|k| chars.get(i + k) == Some(&'#')) {
+                        state = State::Code;
+                        i += hashes;
+                    }
+                }
+                State::TripleQuoted(quote) => {
+                    if c == '\\' {
+                        i += 1;
+                    } else if c == quote && next == Some(quote) && chars.get(i + 2) == Some(&quote)
+                    {
+                        state = State::Code;
+                        i += 2;
+                    }
+                }
+                State::Code => {
+                    if starts_with_at(&chars, i, language.line_comment) {
+                        break;
+                    }
+
+                    if language.syntax == Syntax::Braces && c == '/' && next == Some('*') {
+                        state = State::BlockComment;
+                        i += 2;
+                        continue;
+                    }
+
+                    if let Some((len, hashes)) = raw_string_at(&chars, i, language) {
+                        state = State::RawString('"', hashes);
+                        last = Some('"');
+                        i += len;
+                        continue;
+                    }
+
+                    if language.raw_quotes.contains(&c) {
+                        state = State::RawString(c, 0);
+                    } else if language.syntax == Syntax::Indentation
+                        && language.quotes.contains(&c)
+                        && next == Some(c)
+                        && chars.get(i + 2) == Some(&c)
+                    {
+                        state = State::TripleQuoted(c);
+                        i += 2;
+                    } else if language.quotes.contains(&c) {
+                        state = State::String(c);
+                    } else if c == '\'' {
+                        // skip character literals, e.g. '{' or '\n', but not the lifetimes of rust
+                        if next == Some('\\') {
+                            if let Some(end) = chars[i + 2..].iter().position(|&c| c == '\'') {
+                                i += end + 2;
+                            }
+                        } else if chars.get(i + 2) == Some(&'\'') {
+                            i += 2;
+                        }
+                    } else {
+                        match c {
+                            '(' | '[' | '{' => depth += 1,
+                            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                            _ => {}
+                        }
+                    }
+
+                    if !c.is_whitespace() {
+                        last = Some(c);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        lines.push(Line {
+            text,
+            depth: start_depth,
+            depth_after: depth,
+            in_string,
+            last,
+            indent: text.len() - text.trim_start().len(),
+        });
+    }
+
+    lines
+}
+
+/// Find a raw string literal opening at `idx`, e.g. `r#"` of rust. Returns the length of the opening, and the number of `#`s of the closing.
+fn raw_string_at(chars: &[char], idx: usize, language: &Language) -> Option<(usize, usize)> {
+    let prefix = language.raw_prefix?;
+
+    // rust byte strings are prefixed as well, e.g. `br"`
+    let mut start = idx;
+    if chars[idx] == 'b' && prefix == "r" {
+        start += 1;
+    }
+    // `$` may prefix the raw strings of c#, e.g. `$@"`
+    if !starts_with_at(chars, start, prefix)
+        || (idx > 0 && is_identifier_char(chars[idx - 1]) && chars[idx - 1] != '$')
+    {
+        return None;
+    }
+
+    let mut end = start + prefix.chars().count();
+    let hashes = chars[end..].iter().take_while(|&&c| c == '#').count();
+    end += hashes;
+
+    match chars.get(end) == Some(&'"') {
+        true => Some((end + 1 - idx, hashes)),
+        false => None,
+    }
+}
+
+fn starts_with_at(chars: &[char], idx: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(k, p)| chars.get(idx + k) == Some(&p))
+}
+
+/// The name of a declaration.
+#[derive(Debug)]
+struct Declaration {
+    symbol: String,
+    /// Path of the members declared in the body, e.g. the type of a rust `impl` block
+    scope: String,
+    /// Whether the body declares members, e.g. the methods of a class
+    container: bool,
+}
+impl Declaration {
+    fn new(name: &str, container: bool) -> Self {
+        Self {
+            symbol: name.to_string(),
+            scope: name.to_string(),
+            container,
+        }
+    }
+}
+
+/// A top-level statement or declaration of a block, with its leading comments and attributes.
+#[derive(Debug)]
+struct Item {
+    start: usize,
+    /// Index of the last line (inclusive)
+    end: usize,
+    declaration: Option<Declaration>,
+    body: Option<Body>,
+}
+
+/// The lines of the block of a declaration.
+#[derive(Debug)]
+struct Body {
+    start: usize,
+    /// Index after the last line (exclusive)
+    end: usize,
+    /// Bracket depth or indentation of the lines in the block
+    level: usize,
+}
+
+/// A range of lines to be turned into a chunk.
+#[derive(Debug)]
+struct Piece {
+    start: usize,
+    end: usize,
+    symbol: Option<String>,
+}
+
+struct Splitter<'a> {
+    lines: Vec<Line<'a>>,
+    language: &'static Language,
+    tokenizer: CoreBPE,
+    chunk_capacity: usize,
+}
+impl Splitter<'_> {
+    fn items(&self, from: usize, to: usize, level: usize) -> Vec<Item> {
+        match self.language.syntax {
+            Syntax::Braces => self.brace_items(from, to, level),
+            Syntax::Indentation => self.indented_items(from, to, level),
+        }
+    }
+
+    /// Items end where the brackets are balanced again, at a `}` or `;`, or before a blank line.
+    fn brace_items(&self, from: usize, to: usize, depth: usize) -> Vec<Item> {
+        let mut items = vec![];
+        let mut start = None;
+        for i in from..to {
+            let line = &self.lines[i];
+            if start.is_none() {
+                if line.is_blank() {
+                    continue;
+                }
+                start = Some(i);
+            }
+
+            let next_blank = i + 1 >= to || self.lines[i + 1].is_blank();
+            if line.depth_after <= depth && (matches!(line.last, Some('}' | ';')) || next_blank) {
+                if let Some(start) = start.take() {
+                    items.push(self.brace_item(start, i, depth));
+                }
+            }
+        }
+
+        // the brackets of the last item are unbalanced
+        if let Some(start) = start {
+            let end = self.trim_end(start, to - 1);
+            items.push(self.brace_item(start, end, depth));
+        }
+
+        items
+    }
+
+    fn brace_item(&self, start: usize, end: usize, depth: usize) -> Item {
+        // the block of the declaration opens at the end of its header
+        let header_end = (start..=end)
+            .find(|&k| self.lines[k].last == Some('{') && self.lines[k].depth_after == depth + 1);
+
+        let header = self.lines[start..=header_end.unwrap_or(end)]
+            .iter()
+            .filter(|line| !line.is_decoration(self.language))
+            .take(10)
+            .map(|line| line.text.trim())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let header = header.split('{').next().unwrap_or_default();
+
+        Item {
+            start,
+            end,
+            declaration: declaration(header, self.language),
+            body: header_end.filter(|&k| k + 1 < end).map(|k| Body {
+                start: k + 1,
+                end,
+                level: depth + 1,
+            }),
+        }
+    }
+
+    /// Items end before the next line that is indented no deeper than the item.
+    fn indented_items(&self, from: usize, to: usize, indent: usize) -> Vec<Item> {
+        let mut items = vec![];
+        let mut i = from;
+        while i < to {
+            if self.lines[i].is_blank() {
+                i += 1;
+                continue;
+            }
+            let start = i;
+
+            // decorators and comments lead the statement they annotate
+            while i < to
+                && (self.lines[i].is_blank()
+                    || self.lines[i].is_continued()
+                    || self.lines[i].is_decoration(self.language))
+            {
+                i += 1;
+            }
+            if i == to {
+                items.push(Item {
+                    start,
+                    end: self.trim_end(start, to - 1),
+                    declaration: None,
+                    body: None,
+                });
+                break;
+            }
+
+            let anchor = i;
+            i += 1;
+            while i < to
+                && (self.lines[i].is_blank()
+                    || self.lines[i].is_continued()
+                    || self.lines[i].indent > indent)
+            {
+                i += 1;
+            }
+            let end = self.trim_end(anchor, i - 1);
+
+            let mut header_end = anchor;
+            while header_end < end && self.lines[header_end + 1].is_continued() {
+                header_end += 1;
+            }
+            let header = self.lines[anchor..=header_end]
+                .iter()
+                .map(|line| line.text.trim())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            let body = match self.lines[header_end].last {
+                Some(':') => (header_end + 1..=end)
+                    .find(|&k| !self.lines[k].is_blank() && self.lines[k].indent > indent)
+                    .map(|k| Body {
+                        start: k,
+                        end: end + 1,
+                        level: self.lines[k].indent,
+                    }),
+                _ => None,
+            };
+
+            items.push(Item {
+                start,
+                end,
+                declaration: declaration(&header, self.language),
+                body,
+            });
+        }
+
+        items
+    }
+
+    /// Split an item into pieces of up to `chunk_capacity` tokens.
+    fn split(&self, item: &Item, scope: Option<&str>, pieces: &mut Vec<Piece>) {
+        let symbol = match &item.declaration {
+            Some(declaration) => Some(self.qualify(scope, &declaration.symbol)),
+            None => scope.map(str::to_string),
+        };
+
+        if self.tokens(item.start, item.end) <= self.chunk_capacity {
+            pieces.push(Piece {
+                start: item.start,
+                end: item.end,
+                symbol,
+            });
+            return;
+        }
+
+        // split a large class, module or implementation block at the boundaries of its members
+        let container = item
+            .declaration
+            .as_ref()
+            .is_none_or(|declaration| declaration.container);
+        if let (Some(body), true) = (&item.body, container) {
+            let inner_scope = match &item.declaration {
+                Some(declaration) => Some(self.qualify(scope, &declaration.scope)),
+                None => scope.map(str::to_string),
+            };
+
+            let mut members = vec![];
+            for member in self.items(body.start, body.end, body.level) {
+                self.split(&member, inner_scope.as_deref(), &mut members);
+            }
+
+            if let (Some(first), Some(last)) = (members.first(), members.last()) {
+                // the header leads the first member and the closing lines end the last member if they fit
+                let (first_start, last_end) = (first.start, last.end);
+                match self.tokens(item.start, first.end) <= self.chunk_capacity {
+                    true => members[0].start = item.start,
+                    false => pieces.push(Piece {
+                        start: item.start,
+                        end: first_start - 1,
+                        symbol: symbol.clone(),
+                    }),
+                }
+
+                let closing = match last_end < item.end {
+                    true => {
+                        let last = members.len() - 1;
+                        match self.tokens(members[last].start, item.end) <= self.chunk_capacity {
+                            true => {
+                                members[last].end = item.end;
+                                None
+                            }
+                            false => Some(Piece {
+                                start: last_end + 1,
+                                end: item.end,
+                                symbol,
+                            }),
+                        }
+                    }
+                    false => None,
+                };
+
+                pieces.extend(members);
+                pieces.extend(closing);
+                return;
+            }
+        }
+
+        self.split_lines(item.start, item.end, symbol, pieces);
+    }
+
+    /// Split the lines of a declaration without members, e.g. a long function.
+    fn split_lines(&self, from: usize, to: usize, symbol: Option<String>, pieces: &mut Vec<Piece>) {
+        let mut start = from;
+        let mut tokens = 0;
+        for i in from..=to {
+            let line_tokens = self.tokenizer.encode_ordinary(self.lines[i].text).len() + 1;
+            if i > start && tokens + line_tokens > self.chunk_capacity {
+                pieces.push(Piece {
+                    start,
+                    end: i - 1,
+                    symbol: symbol.clone(),
+                });
+                start = i;
+                tokens = 0;
+            }
+            tokens += line_tokens;
+        }
+
+        pieces.push(Piece {
+            start,
+            end: to,
+            symbol,
+        });
+    }
+
+    /// Merge adjacent pieces of the same symbol, e.g. import statements, as long as they fit in a chunk.
+    fn merge(&self, pieces: Vec<Piece>) -> Vec<Piece> {
+        let mut merged: Vec<Piece> = vec![];
+        for piece in pieces {
+            if let Some(prev) = merged.last_mut() {
+                if prev.symbol == piece.symbol
+                    && self.tokens(prev.start, piece.end) <= self.chunk_capacity
+                {
+                    prev.end = piece.end;
+                    continue;
+                }
+            }
+            merged.push(piece);
+        }
+
+        merged
+    }
+
+    fn to_chunk(&self, piece: Piece) -> Option<DocChunk> {
+        let start = (piece.start..=piece.end).find(|&i| !self.lines[i].is_blank())?;
+        let end = self.trim_end(start, piece.end);
+
+        let mut chunk = DocChunk::new(self.text(start, end));
+        chunk.meta.symbol = piece.symbol;
+        chunk.meta.start_line = Some(start as u32 + 1);
+        chunk.meta.end_line = Some(end as u32 + 1);
+
+        Some(chunk)
+    }
+
+    fn qualify(&self, scope: Option<&str>, name: &str) -> String {
+        match scope {
+            Some(scope) => format!("{}{}{}", scope, self.language.separator, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.lines[start..=end]
+            .iter()
+            .map(|line| line.text)
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    fn tokens(&self, start: usize, end: usize) -> usize {
+        self.tokenizer.encode_ordinary(&self.text(start, end)).len()
+    }
+
+    /// Index of the last non-blank line in `start..=end`.
+    fn trim_end(&self, start: usize, end: usize) -> usize {
+        (start..=end)
+            .rev()
+            .find(|&i| !self.lines[i].is_blank())
+            .unwrap_or(start)
+    }
+}
+
+/// Find the name of the declaration in the header of an item.
+fn declaration(header: &str, language: &Language) -> Option<Declaration> {
+    let words = words(header);
+
+    let first = words
+        .iter()
+        .map(|(_, word)| *word)
+        .find(|word| !MODIFIERS.contains(word))?;
+    if IMPORT_KEYWORDS.contains(&first)
+        || (RESERVED_KEYWORDS.contains(&first) && !DECLARATION_KEYWORDS.contains(&first))
+    {
+        return None;
+    }
+
+    // rust implementation blocks are named after the trait and the type, and scope the members of the type
+    if first == "impl" {
+        let (offset, _) = words.iter().find(|(_, word)| *word == "impl")?;
+        let target = strip_generics(&header[offset + "impl".len()..]);
+        let target = target.split(" where ").next().unwrap_or_default();
+        let target = target.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let scope = target
+            .rsplit(" for ")
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        return Some(Declaration {
+            symbol: format!("impl {}", target),
+            scope,
+            container: true,
+        });
+    }
+
+    for (offset, word) in words.iter() {
+        if !DECLARATION_KEYWORDS.contains(word) {
+            continue;
+        }
+
+        let rest = header[offset + word.len()..].trim_start();
+
+        // go methods are named after the type of their receiver
+        if *word == "func" && rest.starts_with('(') {
+            if let Some(close) = rest.find(')') {
+                let receiver = words_of(&rest[1..close]);
+                let name = leading_identifier(rest[close + 1..].trim_start());
+                if let (Some(receiver), false) = (receiver.last(), name.is_empty()) {
+                    return Some(Declaration {
+                        symbol: format!("{}{}{}", receiver, language.separator, name),
+                        scope: receiver.to_string(),
+                        container: false,
+                    });
+                }
+            }
+        }
+
+        let name = leading_identifier(rest.trim_start_matches(['*', '!']).trim_start());
+        if !name.is_empty() {
+            return Some(Declaration::new(name, CONTAINER_KEYWORDS.contains(word)));
+        }
+    }
+
+    for (offset, word) in words.iter() {
+        if !VARIABLE_KEYWORDS.contains(word) {
+            continue;
+        }
+
+        let rest = header[offset + word.len()..].trim_start();
+        let rest = rest.strip_prefix("mut ").unwrap_or(rest).trim_start();
+        let name = leading_identifier(rest);
+        let after = rest[name.len()..].trim_start();
+        if !name.is_empty() && (after.is_empty() || after.starts_with([':', '=', ';'])) {
+            return Some(Declaration::new(name, false));
+        }
+    }
+
+    // functions and methods declared with their return types, e.g. in c or java
+    if language.syntax == Syntax::Braces {
+        let paren = header.find('(')?;
+        let before = strip_generics(&header[..paren]);
+        let before = before.trim_end();
+        let name = &before[before
+            .rfind(|c: char| !is_identifier_char(c) && !matches!(c, ':' | '.' | '~'))
+            .map(|idx| idx + 1)
+            .unwrap_or(0)..];
+        let name = name.trim_matches(|c| c == ':' || c == '.');
+        let keyword = name.rsplit(['.', ':']).next().unwrap_or_default();
+        if !name.is_empty()
+            && !RESERVED_KEYWORDS.contains(&keyword)
+            && !keyword.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Some(Declaration::new(name, false));
+        }
+    }
+
+    None
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Identifiers of the text with their byte offsets.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (idx, c) in text.char_indices() {
+        match (is_identifier_char(c), start) {
+            (true, None) => start = Some(idx),
+            (false, Some(s)) => {
+                words.push((s, &text[s..idx]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, &text[s..]));
+    }
+
+    words
+}
+
+fn words_of(text: &str) -> Vec<&str> {
+    words(text).into_iter().map(|(_, word)| word).collect()
+}
+
+fn leading_identifier(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !is_identifier_char(c))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// Remove the generic parameters, e.g. `Foo<T>` becomes `Foo`.
+fn strip_generics(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            c if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Symbols and line ranges of the chunks of `source`.
+    fn split(source: &str, ext: &str, chunk_capacity: usize) -> Vec<(Option<String>, u32, u32)> {
+        let tokenizer = super::super::tokenizer().unwrap();
+        chunk_source(source, language(ext).unwrap(), tokenizer, chunk_capacity)
+            .unwrap()
+            .into_iter()
+            .map(|chunk| {
+                (
+                    chunk.meta.symbol,
+                    chunk.meta.start_line.unwrap(),
+                    chunk.meta.end_line.unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn symbol(name: &str, start: u32, end: u32) -> (Option<String>, u32, u32) {
+        (Some(name.to_string()), start, end)
+    }
+
+    #[test]
+    fn languages_by_extension() {
+        assert_eq!(language("c").unwrap().name, "c");
+        assert_eq!(language("h").unwrap().name, "c");
+        assert_eq!(language("cpp").unwrap().name, "c++");
+        assert_eq!(language("hpp").unwrap().name, "c++");
+        assert!(language("txt").is_none());
+    }
+
+    #[test]
+    fn rust_strings_and_comments() {
+        let source = r####"/// Braces in a doc comment: {
+fn raw() -> &'static str {
+    r#"{ "key": "}" }"#
+}
+
+fn raw_bytes() -> &'static [u8] {
+    br##"}"# still inside"##
+}
+
+fn chars() -> char {
+    // a } in a comment
+    /* and { in a block comment */
+    let _lifetime: &'static str = "\"}";
+    '{'
+}
+"####;
+
+        assert_eq!(
+            split(source, "rs", 1000),
+            vec![
+                symbol("raw", 1, 4),
+                symbol("raw_bytes", 6, 8),
+                symbol("chars", 10, 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_impl_block_split_by_methods() {
+        let body =
+            "        let total = values.iter().map(|value| value * 2).sum::<u64>();\n".repeat(4);
+        let source = format!(
+            "impl Server {{\n    fn start(&self) {{\n{body}    }}\n\n    fn stop(&self) {{\n{body}    }}\n}}\n",
+            body = body
+        );
+
+        let chunks = split(&source, "rs", 100);
+        let symbols = chunks
+            .iter()
+            .map(|(symbol, _, _)| symbol.clone().unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(symbols, vec!["Server::start", "Server::stop"]);
+        assert_eq!(chunks[0].1, 1);
+        assert_eq!(chunks[1].2, 15);
+    }
+
+    #[test]
+    fn python_strings_and_nested_blocks() {
+        let source = r#"class Greeter:
+    """A docstring with def fake(): and {"""
+
+    def greet(self, name):
+        # a comment with class Fake:
+        return f"Hello, {name}: 'welcome'"
+
+    def leave(self):
+        return '}'
+
+
+def main():
+    Greeter().greet("world")
+"#;
+
+        assert_eq!(
+            split(source, "py", 1000),
+            vec![symbol("Greeter", 1, 9), symbol("main", 12, 13)]
+        );
+
+        let chunks = split(source, "py", 30);
+        assert!(chunks.contains(&symbol("Greeter.greet", 4, 6)));
+        assert!(chunks.contains(&symbol("Greeter.leave", 8, 9)));
+    }
+
+    #[test]
+    fn javascript_template_literals() {
+        let source = r#"function render(name) {
+  const close = "}";
+  return `<div>${name} } {</div>`;
+}
+
+class View {
+  show() {
+    return '{';
+  }
+}
+"#;
+
+        assert_eq!(
+            split(source, "js", 1000),
+            vec![symbol("render", 1, 4), symbol("View", 6, 10)]
+        );
+    }
+
+    #[test]
+    fn typescript_interfaces() {
+        let source = r#"export interface Options {
+  label: string; // a } in a comment
+}
+
+export async function load(options: Options): Promise<string> {
+  return `{${options.label}`;
+}
+"#;
+
+        assert_eq!(
+            split(source, "ts", 1000),
+            vec![symbol("Options", 1, 3), symbol("load", 5, 7)]
+        );
+    }
+
+    #[test]
+    fn go_raw_strings_and_methods() {
+        let source = r#"func (s *Server) Start() {
+	path := `C:\dir\` + "{"
+	_ = path
+}
+
+func main() {
+	s := &Server{}
+	s.Start()
+}
+"#;
+
+        assert_eq!(
+            split(source, "go", 1000),
+            vec![symbol("Server.Start", 1, 4), symbol("main", 6, 9)]
+        );
+    }
+
+    #[test]
+    fn java_classes() {
+        let source = r#"/** A {@link Object} in a comment */
+public class Greeter {
+    private final String open = "{";
+
+    public String greet(String name) {
+        return open + name + '}';
+    }
+}
+"#;
+
+        assert_eq!(split(source, "java", 1000), vec![symbol("Greeter", 1, 8)]);
+    }
+
+    #[test]
+    fn kotlin_functions() {
+        let source = r#"fun greet(name: String): String {
+    return "Hello, ${name} }"
+}
+
+data class Point(val x: Int, val y: Int)
+"#;
+
+        assert_eq!(
+            split(source, "kt", 1000),
+            vec![symbol("greet", 1, 3), symbol("Point", 5, 5)]
+        );
+    }
+
+    #[test]
+    fn csharp_verbatim_strings() {
+        let source = r#"namespace App
+{
+    public class Paths
+    {
+        public string Root() { return @"C:\dir\"; }
+
+        public string Brace() { return $@"{{}}"; }
+    }
+}
+
+public class Other
+{
+}
+"#;
+
+        assert_eq!(
+            split(source, "cs", 1000),
+            vec![symbol("App", 1, 9), symbol("Other", 11, 13)]
+        );
+    }
+
+    #[test]
+    fn c_functions() {
+        let source = r#"/* a { in a block comment */
+static int count(const char *text) {
+    int n = 0;
+    while (*text++ != '}') {
+        n++;
+    }
+    return n;
+}
+
+int main(void) {
+    return count("{");
+}
+"#;
+
+        assert_eq!(
+            split(source, "c", 1000),
+            vec![symbol("count", 1, 8), symbol("main", 10, 12)]
+        );
+    }
+
+    #[test]
+    fn cpp_namespaces_and_methods() {
+        let source = r#"namespace app {
+int add(int a, int b) {
+    return a + b;
+}
+}
+
+void Server::start() {
+    log("{");
+}
+"#;
+
+        assert_eq!(
+            split(source, "cpp", 1000),
+            vec![symbol("app", 1, 5), symbol("Server::start", 7, 9)]
+        );
+    }
+
+    #[test]
+    fn swift_structs() {
+        let source = r#"struct Greeter {
+    func greet(name: String) -> String {
+        return "Hello, \(name) }"
+    }
+}
+
+func main() {
+    print(Greeter().greet(name: "{"))
+}
+"#;
+
+        assert_eq!(
+            split(source, "swift", 1000),
+            vec![symbol("Greeter", 1, 5), symbol("main", 7, 9)]
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Download documents from `http` and `https` urls.
+
+use super::{is_supported, unsupported_message};
+use crate::error::ServerError;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use url::{Host, Url};
+
+/// Max size (in bytes) of a downloaded document
+pub(crate) const MAX_DOWNLOAD_BYTES: u64 = 64 << 20;
+/// Max duration of each request of a download, from the request to the end of the body
+pub(crate) const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// Max number of redirects followed by a download
+const MAX_REDIRECTS: usize = 10;
+
+/// A downloaded document.
+#[derive(Debug, Clone)]
+pub(crate) struct Download {
+    /// Name of the document, with a supported extension
+    pub(crate) filename: String,
+    pub(crate) bytes: Vec<u8>,
+}
+
+/// Download the document at `url`.
+///
+/// Documents larger than `max_bytes`, by their `Content-Length` or by the bytes actually received, and requests taking longer than [`DOWNLOAD_TIMEOUT`] fail. Hosts resolving to loopback, link-local, private or otherwise non-public addresses are refused unless listed in `allowed_hosts`, and so are the redirects to them.
+pub(crate) async fn fetch(
+    url: &str,
+    max_bytes: u64,
+    allowed_hosts: &[String],
+) -> Result<Download, ServerError> {
+    let parsed = Url::parse(url)
+        .map_err(|e| ServerError::Operation(format!("Invalid url: {}. {}", url, e)))?;
+
+    // redirects are followed here, so that the host of each of them is checked
+    let mut target = parsed.clone();
+    let mut redirects = 0;
+    let mut response = loop {
+        if !matches!(target.scheme(), "http" | "https") {
+            return Err(ServerError::Operation(format!(
+                "Unsupported url scheme: {}. Only http and https are supported.",
+                target.scheme()
+            )));
+        }
+
+        let addrs = resolve(&target, allowed_hosts).await?;
+        let mut builder = reqwest::Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none());
+        // connect to the checked addresses, not to those of a second lookup
+        if let Some(Host::Domain(domain)) = target.host() {
+            builder = builder.resolve_to_addrs(domain, &addrs);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| ServerError::Operation(format!("Failed to download {}. {}", url, e)))?;
+        let response =
+            client.get(target.clone()).send().await.map_err(|e| {
+                ServerError::Operation(format!("Failed to download {}. {}", url, e))
+            })?;
+        if !response.status().is_redirection() {
+            break response;
+        }
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(ServerError::Operation(format!(
+                "Failed to download {}. Too many redirects.",
+                url
+            )));
+        }
+        target = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| target.join(location).ok())
+            .ok_or_else(|| {
+                ServerError::Operation(format!(
+                    "Failed to download {}. Invalid redirect location.",
+                    url
+                ))
+            })?;
+    };
+    if !response.status().is_success() {
+        return Err(ServerError::Operation(format!(
+            "Failed to download {}. Status: {}",
+            url,
+            response.status()
+        )));
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Err(too_large(url, max_bytes));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| {
+            ct.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        });
+    let filename = download_filename(&parsed, content_type.as_deref())
+        .ok_or_else(|| ServerError::Operation(unsupported_message()))?;
+
+    // the body may be longer than its `Content-Length`, or have none
+    let mut bytes = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| ServerError::Operation(format!("Failed to download {}. {}", url, e)))?
+    {
+        if (bytes.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large(url, max_bytes));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(Download { filename, bytes })
+}
+
+/// Resolve the host of `url`. Fails if the host is not in `allowed_hosts` and any of its addresses is not public.
+async fn resolve(url: &Url, allowed_hosts: &[String]) -> Result<Vec<SocketAddr>, ServerError> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| {
+                ServerError::Operation(format!("Failed to resolve the host {}. {}", domain, e))
+            })?
+            .collect(),
+        None => {
+            return Err(ServerError::Operation(format!(
+                "Invalid url: {}. No host.",
+                url
+            )))
+        }
+    };
+
+    let host = url
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if addrs.is_empty() {
+        return Err(ServerError::Operation(format!(
+            "Failed to resolve the host {}.",
+            host
+        )));
+    }
+    if allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Ok(addrs);
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(ServerError::Operation(format!(
+            "Refused to download from {}, which resolves to the non-public address {}. Allow the host with `--download-allowed-hosts`.",
+            host,
+            addr.ip()
+        )));
+    }
+
+    Ok(addrs)
+}
+
+/// Whether the address is reachable on the public internet, i.e. is not a loopback, link-local, private, shared, documentation or otherwise reserved address.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8 "this network"
+        || a == 0
+        // 100.64.0.0/10 shared address space
+        || (a == 100 && (64..128).contains(&b))
+        // 192.0.0.0/24 protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15 benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // 240.0.0.0/4 reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // fc00::/7 unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 link-local
+        || (first & 0xffc0) == 0xfe80
+        // 2001:db8::/32 documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+fn too_large(url: &str, max_bytes: u64) -> ServerError {
+    ServerError::Operation(format!(
+        "Failed to download {}. The document is larger than {} bytes.",
+        url, max_bytes
+    ))
+}
+
+/// Name a downloaded document after the last segment of its url path. If the segment has no supported extension, the extension is guessed from the content type, e.g. `text/html` pages are saved as `.html` files.
+fn download_filename(url: &Url, content_type: Option<&str>) -> Option<String> {
+    let segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .unwrap_or_else(|| url.host_str().unwrap_or("index"));
+
+    // keep the name safe as a file name
+    let name = segment
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect::<String>();
+    let name = name.trim_start_matches('.');
+
+    if is_supported(name) {
+        return Some(name.to_string());
+    }
+
+    let ext = mime_guess::get_mime_extensions_str(content_type?)?
+        .iter()
+        .find(|ext| is_supported(format!("_.{}", ext)))?;
+    let stem = match name.is_empty() {
+        true => "index",
+        false => name,
+    };
+
+    Some(format!("{}.{}", stem, ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serve `response` to one request on a local port, and return the url of `path` on it.
+    async fn serve_once(path: &str, response: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let _ = stream.read(&mut request).await;
+            let _ = stream.write_all(&response).await;
+            let _ = stream.shutdown().await;
+        });
+
+        format!("http://{}{}", addr, path)
+    }
+
+    /// Hosts of the local test servers
+    fn local() -> Vec<String> {
+        vec!["127.0.0.1".to_string()]
+    }
+
+    fn redirect_response(location: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        )
+        .into_bytes()
+    }
+
+    fn http_response(content_type: &str, body: &[u8], content_length: bool) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\n", content_type);
+        if content_length {
+            response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        response.push_str("Connection: close\r\n\r\n");
+
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[tokio::test]
+    async fn fetch_document() {
+        let body = b"Paris is the capital of France.";
+        let url = serve_once("/docs/paris.txt", http_response("text/plain", body, true)).await;
+
+        let download = fetch(&url, MAX_DOWNLOAD_BYTES, &local()).await.unwrap();
+        assert_eq!(download.filename, "paris.txt");
+        assert_eq!(download.bytes, body);
+    }
+
+    #[tokio::test]
+    async fn fetch_page_named_after_content_type() {
+        let body = b"<html><body>Paris</body></html>";
+        let url = serve_once(
+            "/wiki/Paris",
+            http_response("text/html; charset=utf-8", body, true),
+        )
+        .await;
+
+        let download = fetch(&url, MAX_DOWNLOAD_BYTES, &local()).await.unwrap();
+        assert!(matches!(
+            download.filename.as_str(),
+            "Paris.html" | "Paris.htm"
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_content_length_over_limit() {
+        let body = vec![b'a'; 1024];
+        let url = serve_once("/big.txt", http_response("text/plain", &body, true)).await;
+
+        assert!(fetch(&url, 512, &local()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_body_over_limit() {
+        let body = vec![b'a'; 1024];
+        let url = serve_once("/big.txt", http_response("text/plain", &body, false)).await;
+
+        assert!(fetch(&url, 512, &local()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_unsupported_scheme() {
+        assert!(fetch("ftp://localhost/paris.txt", MAX_DOWNLOAD_BYTES, &[])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_non_public_hosts() {
+        for url in [
+            "http://127.0.0.1/paris.txt",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.1/paris.txt",
+            "http://192.168.1.1/paris.txt",
+            "http://[::1]/paris.txt",
+            "http://[::ffff:127.0.0.1]/paris.txt",
+            "http://0.0.0.0/paris.txt",
+        ] {
+            let err = fetch(url, MAX_DOWNLOAD_BYTES, &[]).await.unwrap_err();
+            assert!(err.to_string().contains("non-public"), "{}: {}", url, err);
+        }
+    }
+
+    #[tokio::test]
+    async fn follow_redirects_to_allowed_hosts() {
+        let body = b"Paris is the capital of France.";
+        let target = serve_once("/paris.txt", http_response("text/plain", body, true)).await;
+        let url = serve_once("/docs/paris.txt", redirect_response(&target)).await;
+
+        let download = fetch(&url, MAX_DOWNLOAD_BYTES, &local()).await.unwrap();
+        assert_eq!(download.filename, "paris.txt");
+        assert_eq!(download.bytes, body);
+    }
+
+    #[tokio::test]
+    async fn reject_redirects_to_non_public_hosts() {
+        let url = serve_once(
+            "/paris.txt",
+            redirect_response("http://169.254.169.254/latest/meta-data/"),
+        )
+        .await;
+
+        let err = fetch(&url, MAX_DOWNLOAD_BYTES, &local()).await.unwrap_err();
+        assert!(err.to_string().contains("non-public"));
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn name_after_last_segment() {
+        let url = Url::parse("http://localhost/a/b/notes.md/").unwrap();
+        assert_eq!(download_filename(&url, None).as_deref(), Some("notes.md"));
+
+        let url = Url::parse("http://localhost/a/page?id=1").unwrap();
+        assert_eq!(download_filename(&url, None), None);
+        assert_eq!(
+            download_filename(&url, Some("text/markdown")).as_deref(),
+            Some("page.md")
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements that never hold the content of a page.
+const SKIPPED_ELEMENTS: [&str; 13] = [
+    "script", "style", "noscript", "template", "nav", "footer", "aside", "form", "iframe", "svg",
+    "canvas", "button", "select",
+];
+
+/// ARIA roles of page navigation and boilerplate.
+const SKIPPED_ROLES: [&str; 5] = [
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+];
+
+/// Convert an html page to markdown text.
+///
+/// Scripts, styles and navigation are stripped. Headings, lists and tables are kept as markdown so that the structure of the page survives chunking.
+pub(crate) fn to_markdown(html: &str) -> String {
+    let document = Html::parse_document(html);
+
+    // prefer the main content of the page if it is marked up
+    let root = ["main", "article", "body"]
+        .iter()
+        .filter_map(|name| Selector::parse(name).ok())
+        .find_map(|selector| document.select(&selector).next())
+        .unwrap_or_else(|| document.root_element());
+
+    let mut text = String::new();
+    render_children(root, &mut text);
+
+    normalize(&text)
+}
+
+fn render_children(element: ElementRef, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => push_inline(out, text),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    render_element(child, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn render_element(element: ElementRef, out: &mut String) {
+    let value = element.value();
+    let name = value.name();
+
+    if SKIPPED_ELEMENTS.contains(&name)
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+        || value
+            .attr("role")
+            .is_some_and(|role| SKIPPED_ROLES.contains(&role))
+    {
+        return;
+    }
+
+    match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap_or(1);
+            let heading = collapse_whitespace(&element.text().collect::<String>());
+            if !heading.is_empty() {
+                out.push_str("\n\n");
+                out.push_str(&"#".repeat(level));
+                out.push(' ');
+                out.push_str(&heading);
+                out.push_str("\n\n");
+            }
+        }
+        "table" => render_table(element, out),
+        "pre" => {
+            out.push_str("\n\n```\n");
+            out.push_str(element.text().collect::<String>().trim_end());
+            out.push_str("\n```\n\n");
+        }
+        "li" => {
+            out.push_str("\n- ");
+            render_children(element, out);
+        }
+        "br" => out.push('\n'),
+        "p" | "div" | "section" | "article" | "main" | "header" | "ul" | "ol" | "dl" | "dt"
+        | "dd" | "blockquote" | "figure" | "figcaption" | "hr" => {
+            out.push_str("\n\n");
+            render_children(element, out);
+            out.push_str("\n\n");
+        }
+        _ => render_children(element, out),
+    }
+}
+
+/// Render a table as a markdown table, one row per line.
+fn render_table(table: ElementRef, out: &mut String) {
+    let rows = match Selector::parse("tr") {
+        Ok(selector) => table.select(&selector).collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+
+    out.push_str("\n\n");
+    for (idx, row) in rows.iter().enumerate() {
+        let cells = row
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|cell| matches!(cell.value().name(), "th" | "td"))
+            .map(|cell| collapse_whitespace(&cell.text().collect::<String>()).replace('|', "\\|"))
+            .collect::<Vec<String>>();
+
+        if cells.is_empty() {
+            continue;
+        }
+
+        out.push_str("| ");
+        out.push_str(&cells.join(" | "));
+        out.push_str(" |\n");
+
+        // the first row is taken as the header of the table
+        if idx == 0 {
+            out.push('|');
+            out.push_str(&" --- |".repeat(cells.len()));
+            out.push('\n');
+        }
+    }
+    out.push('\n');
+}
+
+fn push_inline(out: &mut String, text: &str) {
+    let collapsed = collapse_whitespace(text);
+    if collapsed.is_empty() {
+        if text.chars().any(char::is_whitespace) && !out.ends_with(char::is_whitespace) {
+            out.push(' ');
+        }
+        return;
+    }
+
+    if text.starts_with(char::is_whitespace) && !out.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    out.push_str(&collapsed);
+    if text.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Trim the lines and squeeze the blank lines between blocks. Lines of code blocks keep their indentation.
+fn normalize(text: &str) -> String {
+    let mut normalized = String::new();
+    let mut blank_lines = 0;
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim() == "```" {
+            in_code_block = !in_code_block;
+        }
+
+        let line = match in_code_block {
+            true => line.trim_end(),
+            false => line.trim(),
+        };
+        if line.is_empty() && !in_code_block {
+            blank_lines += 1;
+            continue;
+        }
+
+        if !normalized.is_empty() {
+            normalized.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        normalized.push_str(line);
+        blank_lines = 0;
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_boilerplate() {
+        let html = r#"<html>
+<head><title>Paris</title><style>body { color: red; }</style></head>
+<body>
+  <nav><a href="/">Home</a> <a href="/cities">Cities</a></nav>
+  <div role="banner">Site banner</div>
+  <script>var tracking = "do not index";</script>
+  <p>Paris is the capital of France.</p>
+  <noscript>Enable javascript</noscript>
+  <aside>Related cities</aside>
+  <p hidden>Hidden text</p>
+  <footer>Copyright 2024</footer>
+</body>
+</html>"#;
+
+        assert_eq!(to_markdown(html), "Paris is the capital of France.");
+    }
+
+    #[test]
+    fn prefer_main_content() {
+        let html = r#"<body>
+  <div>Sign in to continue</div>
+  <main><p>Only the main content is kept.</p></main>
+</body>"#;
+
+        assert_eq!(to_markdown(html), "Only the main content is kept.");
+    }
+
+    #[test]
+    fn extract_headings() {
+        let html = r#"<body>
+  <h1>France</h1>
+  <p>A country in Europe.</p>
+  <h2>
+    Paris
+  </h2>
+  <p>The <b>capital</b> of France.</p>
+  <h3></h3>
+</body>"#;
+
+        assert_eq!(
+            to_markdown(html),
+            "# France\n\nA country in Europe.\n\n## Paris\n\nThe capital of France."
+        );
+    }
+
+    #[test]
+    fn keep_lists_tables_and_code() {
+        let html = r#"<body>
+  <ul><li>Paris</li><li>Lyon</li></ul>
+  <table>
+    <tr><th>City</th><th>Population</th></tr>
+    <tr><td>Paris</td><td>2 102 650</td></tr>
+  </table>
+  <pre>fn main() {
+    println!("Paris");
+}</pre>
+</body>"#;
+
+        assert_eq!(
+            to_markdown(html),
+            "- Paris\n- Lyon\n\n| City | Population |\n| --- | --- |\n| Paris | 2 102 650 |\n\n```\nfn main() {\n    println!(\"Paris\");\n}\n```"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Turn uploaded documents into chunks of text ready for computing embeddings.
+
+pub(crate) mod archive;
+mod code;
+pub(crate) mod download;
+mod html;
+mod office;
+mod pdf;
+mod structured;
+
+use crate::error::ServerError;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+/// Extensions of the documents that can be chunked.
+pub(crate) const SUPPORTED_EXTENSIONS: [&str; 10] = [
+    "txt", "md", "pdf", "html", "htm", "docx", "odt", "csv", "json", "jsonl",
+];
+
+/// Metadata kept with a chunk and persisted in the payload of its point.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ChunkMeta {
+    /// Id of the archived file the chunk comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) file_id: Option<String>,
+    /// Name of the archived file the chunk comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) filename: Option<String>,
+    /// Unix timestamp (in seconds) of the upload of the archived file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<u64>,
+    /// Key/value tags of the archived file, set on upload
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: BTreeMap<String, String>,
+    /// SHA-256 checksum of the document the chunk comes from, recorded for the watched documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+    /// Position (0-based) of the chunk in the document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) chunk_index: Option<usize>,
+    /// Byte offset of the start of the chunk in the text of the document, or of the page for paged documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) start_byte: Option<usize>,
+    /// Byte offset of the end (exclusive) of the chunk in the text of the document, or of the page for paged documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) end_byte: Option<usize>,
+    /// Page number (1-based) of paged documents, e.g. PDF
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) page: Option<u32>,
+    /// Name of the function, class or implementation block of source code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) symbol: Option<String>,
+    /// First line (1-based) of the chunk in source code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) start_line: Option<u32>,
+    /// Last line (1-based) of the chunk in source code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) end_line: Option<u32>,
+}
+
+/// A piece of a document.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DocChunk {
+    pub(crate) text: String,
+    pub(crate) meta: ChunkMeta,
+}
+impl DocChunk {
+    fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            meta: ChunkMeta::default(),
+        }
+    }
+}
+
+/// Record the document the chunks come from, i.e. the file id, filename, upload time and tags of `document`, in the metadata of every chunk.
+pub(crate) fn set_document(chunks: &mut [DocChunk], document: &ChunkMeta) {
+    for chunk in chunks.iter_mut() {
+        chunk.meta.file_id.clone_from(&document.file_id);
+        chunk.meta.filename.clone_from(&document.filename);
+        chunk.meta.created_at = document.created_at;
+        chunk.meta.tags.clone_from(&document.tags);
+        chunk.meta.checksum.clone_from(&document.checksum);
+    }
+}
+
+/// Check if the given file can be chunked by its extension.
+pub(crate) fn is_supported(filename: impl AsRef<str>) -> bool {
+    match extension(filename.as_ref()) {
+        Some(ext) => SUPPORTED_EXTENSIONS.contains(&ext.as_str()) || code::language(&ext).is_some(),
+        None => false,
+    }
+}
+
+/// Message returned when the given file is not supported.
+pub(crate) fn unsupported_message() -> String {
+    format!(
+        "Failed to upload the target file. Only files with {} extensions and source files with {} extensions are supported.",
+        SUPPORTED_EXTENSIONS
+            .iter()
+            .map(|ext| format!("'{}'", ext))
+            .collect::<Vec<String>>()
+            .join(", "),
+        code::extensions()
+            .map(|ext| format!("'{}'", ext))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+/// Read the file at `path` and split it into chunks. Each chunk contains up to `chunk_capacity` tokens.
+pub(crate) fn chunk_file(
+    path: impl AsRef<Path>,
+    chunk_capacity: usize,
+) -> Result<Vec<DocChunk>, ServerError> {
+    let path = path.as_ref();
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let ext = match extension(&filename) {
+        Some(ext) => ext,
+        None => {
+            return Err(ServerError::Operation(format!(
+                "Failed to get the extension of the archived `{}`.",
+                &filename
+            )))
+        }
+    };
+
+    let bytes = fs::read(path)
+        .map_err(|e| ServerError::Operation(format!("Failed to read `{}`. {}", &filename, e)))?;
+
+    let mut chunks = match ext.as_str() {
+        "txt" | "md" => {
+            let contents = String::from_utf8(bytes).map_err(|e| {
+                ServerError::Operation(format!("Failed to read `{}`. {}", &filename, e))
+            })?;
+
+            let mut chunks = chunk_text(&contents, &ext, chunk_capacity)?
+                .into_iter()
+                .map(DocChunk::new)
+                .collect::<Vec<DocChunk>>();
+            locate(&mut chunks, &contents);
+
+            chunks
+        }
+        "pdf" => {
+            println!("[+] Extracting the text of the pdf file ...");
+
+            let pages = pdf::extract_pages(&bytes)?;
+
+            println!("    * Number of pages: {}", pages.len());
+
+            let mut chunks = vec![];
+            for (page, text) in pages {
+                if text.trim().is_empty() {
+                    continue;
+                }
+
+                let mut page_chunks = chunk_text(&text, "txt", chunk_capacity)?
+                    .into_iter()
+                    .map(DocChunk::new)
+                    .collect::<Vec<DocChunk>>();
+                locate(&mut page_chunks, &text);
+                for mut chunk in page_chunks {
+                    chunk.meta.page = Some(page);
+                    chunks.push(chunk);
+                }
+            }
+
+            chunks
+        }
+        "html" | "htm" => {
+            println!("[+] Extracting the text of the html file ...");
+
+            let html = String::from_utf8_lossy(&bytes);
+            let markdown = html::to_markdown(&html);
+
+            let mut chunks = chunk_text(&markdown, "md", chunk_capacity)?
+                .into_iter()
+                .map(DocChunk::new)
+                .collect::<Vec<DocChunk>>();
+            locate(&mut chunks, &markdown);
+
+            chunks
+        }
+        "docx" | "odt" => {
+            println!("[+] Extracting the text of the office document ...");
+
+            let markdown = match ext.as_str() {
+                "docx" => office::docx_to_markdown(&bytes)?,
+                _ => office::odt_to_markdown(&bytes)?,
+            };
+
+            let mut chunks = chunk_text(&markdown, "md", chunk_capacity)?
+                .into_iter()
+                .map(DocChunk::new)
+                .collect::<Vec<DocChunk>>();
+            locate(&mut chunks, &markdown);
+
+            chunks
+        }
+        "csv" | "json" | "jsonl" => {
+            // rows and records are never split across chunks
+            let chunks = match ext.as_str() {
+                "csv" => structured::chunk_csv(&bytes, chunk_capacity)?,
+                "json" => structured::chunk_json(&bytes, chunk_capacity)?,
+                _ => structured::chunk_jsonl(&bytes, chunk_capacity)?,
+            };
+
+            chunks.into_iter().map(DocChunk::new).collect()
+        }
+        ext => match code::language(ext) {
+            Some(language) => {
+                let source = String::from_utf8(bytes).map_err(|e| {
+                    ServerError::Operation(format!("Failed to read `{}`. {}", &filename, e))
+                })?;
+
+                let mut chunks =
+                    code::chunk_source(&source, language, tokenizer()?, chunk_capacity)?;
+                locate(&mut chunks, &source);
+
+                chunks
+            }
+            None => return Err(ServerError::Operation(unsupported_message())),
+        },
+    };
+
+    for (idx, chunk) in chunks.iter_mut().enumerate() {
+        chunk.meta.chunk_index = Some(idx);
+    }
+
+    Ok(chunks)
+}
+
+/// Record the byte range of each chunk in `text`, the text the chunks are split from. Chunks not found verbatim in order have no range.
+fn locate(chunks: &mut [DocChunk], text: &str) {
+    let mut cursor = 0;
+    for chunk in chunks.iter_mut() {
+        if let Some(pos) = text[cursor..].find(chunk.text.as_str()) {
+            let start = cursor + pos;
+            chunk.meta.start_byte = Some(start);
+            chunk.meta.end_byte = Some(start + chunk.text.len());
+            cursor = start + chunk.text.len();
+        }
+    }
+}
+
+/// Split plain text (`txt`) or markdown (`md`) content into chunks with the splitter of `llama-core`.
+fn chunk_text(text: &str, ty: &str, chunk_capacity: usize) -> Result<Vec<String>, ServerError> {
+    llama_core::rag::chunk_text(text, ty, chunk_capacity)
+        .map_err(|e| ServerError::Operation(e.to_string()))
+}
+
+/// Tokenizer measuring the chunks. `llama-core` splits text by the same encoding.
+pub(crate) fn tokenizer() -> Result<CoreBPE, ServerError> {
+    cl100k_base().map_err(|e| ServerError::Operation(e.to_string()))
+}
+
+fn extension(filename: &str) -> Option<String> {
+    Path::new(filename)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .map(|ext| ext.to_lowercase())
+}
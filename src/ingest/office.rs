@@ -0,0 +1,353 @@
+use crate::error::ServerError;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use std::io::{Cursor, Read};
+
+/// Names of the xml elements that carry the text of an office document.
+struct Markup {
+    /// Path of the xml document in the zip container
+    entry: &'static str,
+    /// Elements of paragraphs and headings
+    paragraphs: &'static [&'static [u8]],
+    /// Element of text runs. If `None`, all text inside a paragraph is collected.
+    text: Option<&'static [u8]>,
+    /// Elements rendered as a whitespace
+    spaces: &'static [&'static [u8]],
+    /// Elements rendered as a line break
+    line_breaks: &'static [&'static [u8]],
+    table: &'static [u8],
+    row: &'static [u8],
+    cell: &'static [u8],
+    /// Heading level of the paragraph declared by the given element
+    heading_level: fn(&BytesStart) -> Option<usize>,
+}
+
+/// Office Open XML (`docx`)
+const DOCX: Markup = Markup {
+    entry: "word/document.xml",
+    paragraphs: &[b"w:p"],
+    text: Some(b"w:t"),
+    spaces: &[b"w:tab"],
+    line_breaks: &[b"w:br", b"w:cr"],
+    table: b"w:tbl",
+    row: b"w:tr",
+    cell: b"w:tc",
+    heading_level: docx_heading_level,
+};
+
+/// OpenDocument Text (`odt`)
+const ODT: Markup = Markup {
+    entry: "content.xml",
+    paragraphs: &[b"text:p", b"text:h"],
+    text: None,
+    spaces: &[b"text:s", b"text:tab"],
+    line_breaks: &[b"text:line-break"],
+    table: b"table:table",
+    row: b"table:table-row",
+    cell: b"table:table-cell",
+    heading_level: odt_heading_level,
+};
+
+/// Extract the paragraphs, headings and tables of a `docx` document as markdown text.
+pub(crate) fn docx_to_markdown(bytes: &[u8]) -> Result<String, ServerError> {
+    to_markdown(bytes, &DOCX)
+}
+
+/// Extract the paragraphs, headings and tables of an `odt` document as markdown text.
+pub(crate) fn odt_to_markdown(bytes: &[u8]) -> Result<String, ServerError> {
+    to_markdown(bytes, &ODT)
+}
+
+fn to_markdown(bytes: &[u8], markup: &Markup) -> Result<String, ServerError> {
+    let xml = read_entry(bytes, markup.entry)?;
+
+    let mut reader = Reader::from_str(&xml);
+
+    let mut out = String::new();
+    let mut paragraph = String::new();
+    let mut heading: Option<usize> = None;
+    let mut in_paragraph = false;
+    let mut in_text = false;
+
+    // tables nested in a cell are flattened into the cell of the outermost table
+    let mut table_depth = 0;
+    let mut row: Vec<String> = vec![];
+    let mut cell = String::new();
+    let mut rows_in_table = 0;
+
+    loop {
+        let event = reader.read_event().map_err(|e| {
+            ServerError::Operation(format!("Failed to parse `{}`. {}", markup.entry, e))
+        })?;
+
+        match event {
+            Event::Start(e) => {
+                let name = e.name();
+                let name = name.as_ref();
+
+                if markup.paragraphs.contains(&name) {
+                    in_paragraph = true;
+                    paragraph.clear();
+                    heading = None;
+                } else if Some(name) == markup.text {
+                    in_text = true;
+                } else if name == markup.table {
+                    table_depth += 1;
+                    if table_depth == 1 {
+                        rows_in_table = 0;
+                        out.push_str("\n\n");
+                    }
+                } else if name == markup.row && table_depth == 1 {
+                    row.clear();
+                } else if name == markup.cell && table_depth == 1 {
+                    cell.clear();
+                }
+
+                if let Some(level) = (markup.heading_level)(&e) {
+                    heading = Some(level);
+                }
+            }
+            Event::Empty(e) => {
+                let name = e.name();
+                let name = name.as_ref();
+
+                if in_paragraph && markup.spaces.contains(&name) {
+                    paragraph.push(' ');
+                } else if in_paragraph && markup.line_breaks.contains(&name) {
+                    paragraph.push('\n');
+                } else if name == markup.cell && table_depth == 1 {
+                    row.push(String::new());
+                }
+
+                if let Some(level) = (markup.heading_level)(&e) {
+                    heading = Some(level);
+                }
+            }
+            Event::Text(text) => {
+                let collect = match markup.text {
+                    Some(_) => in_text,
+                    None => in_paragraph,
+                };
+                if collect {
+                    let text = text.unescape().map_err(|e| {
+                        ServerError::Operation(format!("Failed to parse `{}`. {}", markup.entry, e))
+                    })?;
+                    paragraph.push_str(&text);
+                }
+            }
+            Event::End(e) => {
+                let name = e.name();
+                let name = name.as_ref();
+
+                if markup.paragraphs.contains(&name) {
+                    in_paragraph = false;
+
+                    let text = paragraph.trim();
+                    if text.is_empty() {
+                        continue;
+                    }
+
+                    if table_depth > 0 {
+                        if !cell.is_empty() {
+                            cell.push(' ');
+                        }
+                        cell.push_str(&text.split_whitespace().collect::<Vec<&str>>().join(" "));
+                    } else {
+                        out.push_str("\n\n");
+                        if let Some(level) = heading {
+                            out.push_str(&"#".repeat(level.clamp(1, 6)));
+                            out.push(' ');
+                        }
+                        out.push_str(text);
+                        out.push_str("\n\n");
+                    }
+                } else if Some(name) == markup.text {
+                    in_text = false;
+                } else if name == markup.cell && table_depth == 1 {
+                    row.push(cell.replace('|', "\\|"));
+                    cell.clear();
+                } else if name == markup.row && table_depth == 1 {
+                    if row.iter().all(|cell| cell.is_empty()) {
+                        continue;
+                    }
+
+                    out.push_str("| ");
+                    out.push_str(&row.join(" | "));
+                    out.push_str(" |\n");
+
+                    // the first row is taken as the header of the table
+                    if rows_in_table == 0 {
+                        out.push('|');
+                        out.push_str(&" --- |".repeat(row.len()));
+                        out.push('\n');
+                    }
+                    rows_in_table += 1;
+                } else if name == markup.table && table_depth > 0 {
+                    table_depth -= 1;
+                    if table_depth == 0 {
+                        out.push('\n');
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    // squeeze the blank lines between blocks
+    let mut markdown = String::new();
+    for block in out.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+        if !markdown.is_empty() {
+            markdown.push_str("\n\n");
+        }
+        markdown.push_str(block);
+    }
+
+    Ok(markdown)
+}
+
+fn read_entry(bytes: &[u8], name: &str) -> Result<String, ServerError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| {
+        ServerError::Operation(format!("Failed to open the office document. {}", e))
+    })?;
+
+    let mut entry = archive.by_name(name).map_err(|e| {
+        ServerError::Operation(format!(
+            "Not found `{}` in the office document. {}",
+            name, e
+        ))
+    })?;
+
+    let mut xml = String::new();
+    entry
+        .read_to_string(&mut xml)
+        .map_err(|e| ServerError::Operation(format!("Failed to read `{}`. {}", name, e)))?;
+
+    Ok(xml)
+}
+
+/// Paragraphs of `docx` documents declare headings with the `Title` and `Heading<N>` styles.
+fn docx_heading_level(e: &BytesStart) -> Option<usize> {
+    if e.name().as_ref() != b"w:pStyle" {
+        return None;
+    }
+
+    let style = attribute(e, b"w:val")?.to_lowercase();
+    if style == "title" {
+        return Some(1);
+    }
+
+    style
+        .strip_prefix("heading")
+        .and_then(|level| level.trim().parse::<usize>().ok())
+}
+
+/// Headings of `odt` documents are `text:h` elements with the `text:outline-level` attribute.
+fn odt_heading_level(e: &BytesStart) -> Option<usize> {
+    if e.name().as_ref() != b"text:h" {
+        return None;
+    }
+
+    Some(
+        attribute(e, b"text:outline-level")
+            .and_then(|level| level.parse::<usize>().ok())
+            .unwrap_or(1),
+    )
+}
+
+fn attribute(e: &BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes()
+        .filter_map(Result::ok)
+        .find(|attr| attr.key.as_ref() == key)
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Build a zip container holding `xml` as the entry `name`.
+    fn container(name: &str, xml: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .start_file(name, zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(xml.as_bytes()).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn docx_paragraphs_and_headings() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:body>
+    <w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>France</w:t></w:r></w:p>
+    <w:p><w:r><w:t xml:space="preserve">Paris is the </w:t></w:r><w:r><w:t>capital &amp; largest city.</w:t></w:r></w:p>
+    <w:p></w:p>
+    <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Cities</w:t></w:r></w:p>
+    <w:p><w:r><w:t>Lyon</w:t><w:tab/><w:t>Marseille</w:t><w:br/><w:t>Lille</w:t></w:r></w:p>
+  </w:body>
+</w:document>"#;
+
+        let markdown = docx_to_markdown(&container("word/document.xml", xml)).unwrap();
+        assert_eq!(
+            markdown,
+            "# France\n\nParis is the capital & largest city.\n\n## Cities\n\nLyon Marseille\nLille"
+        );
+    }
+
+    #[test]
+    fn docx_tables() {
+        let xml = r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:body>
+    <w:tbl>
+      <w:tr><w:tc><w:p><w:r><w:t>City</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Country</w:t></w:r></w:p></w:tc></w:tr>
+      <w:tr><w:tc><w:p><w:r><w:t>Paris</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>France</w:t></w:r></w:p></w:tc></w:tr>
+    </w:tbl>
+    <w:p><w:r><w:t>After the table.</w:t></w:r></w:p>
+  </w:body>
+</w:document>"#;
+
+        let markdown = docx_to_markdown(&container("word/document.xml", xml)).unwrap();
+        assert_eq!(
+            markdown,
+            "| City | Country |\n| --- | --- |\n| Paris | France |\n\nAfter the table."
+        );
+    }
+
+    #[test]
+    fn odt_paragraphs_and_headings() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0">
+  <office:body>
+    <office:text>
+      <text:h text:outline-level="1">France</text:h>
+      <text:p>Paris is the <text:span>capital</text:span> of France.</text:p>
+      <text:h text:outline-level="2">Cities</text:h>
+      <text:p>Lyon<text:s/>and<text:tab/>Lille<text:line-break/>Marseille</text:p>
+      <table:table>
+        <table:table-row><table:table-cell><text:p>City</text:p></table:table-cell><table:table-cell><text:p>Country</text:p></table:table-cell></table:table-row>
+        <table:table-row><table:table-cell><text:p>Paris</text:p></table:table-cell><table:table-cell/></table:table-row>
+      </table:table>
+    </office:text>
+  </office:body>
+</office:document-content>"#;
+
+        let markdown = odt_to_markdown(&container("content.xml", xml)).unwrap();
+        assert_eq!(
+            markdown,
+            "# France\n\nParis is the capital of France.\n\n## Cities\n\nLyon and Lille\nMarseille\n\n| City | Country |\n| --- | --- |\n| Paris |  |"
+        );
+    }
+
+    #[test]
+    fn reject_invalid_documents() {
+        assert!(docx_to_markdown(b"not a zip").is_err());
+        assert!(docx_to_markdown(&container("content.xml", "<a/>")).is_err());
+        assert!(odt_to_markdown(&container("content.xml", "<a><b></a>")).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::ServerError;
+use lopdf::Document;
+
+/// Extract the text of a pdf document page by page.
+///
+/// Returns the pairs of the page number (1-based) and the text on the page. Pages whose content cannot be decoded are skipped.
+pub(crate) fn extract_pages(bytes: &[u8]) -> Result<Vec<(u32, String)>, ServerError> {
+    let doc = Document::load_mem(bytes)
+        .map_err(|e| ServerError::Operation(format!("Failed to load the pdf file. {}", e)))?;
+
+    if doc.is_encrypted() {
+        return Err(ServerError::Operation(
+            "Failed to load the pdf file. Encrypted pdf files are not supported.".to_string(),
+        ));
+    }
+
+    let mut pages = vec![];
+    for page in doc.get_pages().into_keys() {
+        match doc.extract_text(&[page]) {
+            Ok(text) => pages.push((page, text)),
+            Err(e) => println!("    * [WARNING] Skip page {}. {}", page, e),
+        }
+    }
+
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{
+        content::{Content, Operation},
+        dictionary, Object, Stream,
+    };
+
+    /// Show `text` at `(x, y)` in a text object of its own.
+    fn show(x: i64, y: i64, text: &str) -> Vec<Operation> {
+        vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 12.into()]),
+            Operation::new("Td", vec![x.into(), y.into()]),
+            Operation::new("Tj", vec![Object::string_literal(text)]),
+            Operation::new("ET", vec![]),
+        ]
+    }
+
+    /// Build a pdf document of a page per list of operations.
+    fn pdf(pages: Vec<Vec<Operation>>, encrypted: bool) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        let mut kids = vec![];
+        for operations in pages {
+            let content = Content { operations }.encode().unwrap();
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            });
+            kids.push(page_id.into());
+        }
+        let count = kids.len() as i64;
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => count,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        if encrypted {
+            let encrypt_id = doc.add_object(dictionary! {
+                "Filter" => "Standard",
+                "V" => 1,
+                "R" => 2,
+            });
+            doc.trailer.set("Encrypt", encrypt_id);
+        }
+
+        let mut bytes = vec![];
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn extract_text_by_page() {
+        let bytes = pdf(
+            vec![
+                show(72, 700, "Paris is the capital of France."),
+                show(72, 700, "Berlin is the capital of Germany."),
+            ],
+            false,
+        );
+
+        let pages = extract_pages(&bytes).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].0, 1);
+        assert_eq!(pages[0].1.trim(), "Paris is the capital of France.");
+        assert_eq!(pages[1].0, 2);
+        assert_eq!(pages[1].1.trim(), "Berlin is the capital of Germany.");
+    }
+
+    #[test]
+    fn keep_the_lines_of_each_column_together() {
+        // the left column is written before the right one, line by line
+        let mut operations = vec![];
+        operations.extend(show(72, 700, "Left column, first line."));
+        operations.extend(show(72, 686, "Left column, second line."));
+        operations.extend(show(320, 700, "Right column, first line."));
+        operations.extend(show(320, 686, "Right column, second line."));
+        let bytes = pdf(vec![operations], false);
+
+        let pages = extract_pages(&bytes).unwrap();
+        let lines = pages[0].1.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            lines,
+            [
+                "Left column, first line.",
+                "Left column, second line.",
+                "Right column, first line.",
+                "Right column, second line.",
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_pages_without_text() {
+        let bytes = pdf(
+            vec![vec![], show(72, 700, "Only the second page has text.")],
+            false,
+        );
+
+        let pages = extract_pages(&bytes).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].1.trim().is_empty());
+        assert_eq!(pages[1].1.trim(), "Only the second page has text.");
+    }
+
+    #[test]
+    fn reject_encrypted_documents() {
+        let bytes = pdf(vec![show(72, 700, "Secret.")], true);
+
+        let err = extract_pages(&bytes).unwrap_err();
+        assert!(err.to_string().contains("Encrypted"));
+    }
+
+    #[test]
+    fn reject_invalid_documents() {
+        assert!(extract_pages(b"not a pdf").is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use crate::error::ServerError;
+use serde_json::Value;
+use tiktoken_rs::CoreBPE;
+
+/// Rows of a table or records of a json document, rendered as `column: value` lines.
+#[derive(Debug, Default)]
+struct Records {
+    columns: Vec<String>,
+    rows: Vec<Vec<(String, String)>>,
+}
+impl Records {
+    fn push(&mut self, row: Vec<(String, String)>) {
+        for (column, _) in row.iter() {
+            if !self.columns.contains(column) {
+                self.columns.push(column.clone());
+            }
+        }
+        self.rows.push(row);
+    }
+}
+
+/// Split a csv file into chunks of whole rows.
+pub(crate) fn chunk_csv(bytes: &[u8], chunk_capacity: usize) -> Result<Vec<String>, ServerError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(bytes);
+
+    let headers = reader
+        .headers()
+        .map_err(|e| ServerError::Operation(format!("Failed to read the csv header. {}", e)))?
+        .iter()
+        .map(|header| header.trim().to_string())
+        .collect::<Vec<String>>();
+
+    let mut records = Records {
+        columns: headers.clone(),
+        rows: vec![],
+    };
+    for (idx, row) in reader.records().enumerate() {
+        let row = row.map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to read row {} of the csv file. {}",
+                idx + 1,
+                e
+            ))
+        })?;
+
+        records.push(
+            row.iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let column = match headers.get(i) {
+                        Some(header) if !header.is_empty() => header.clone(),
+                        _ => format!("column_{}", i + 1),
+                    };
+                    (column, value.trim().to_string())
+                })
+                .collect(),
+        );
+    }
+
+    pack(records, chunk_capacity)
+}
+
+/// Split a json file into chunks of whole records. A top-level array holds one record per element. An object wrapping a single array of records, e.g. `{"data": [...]}`, holds the records of that array, plus a record of its other fields if any. Any other value is a single record.
+pub(crate) fn chunk_json(bytes: &[u8], chunk_capacity: usize) -> Result<Vec<String>, ServerError> {
+    let value: Value = serde_json::from_slice(bytes)
+        .map_err(|e| ServerError::Operation(format!("Failed to parse the json file. {}", e)))?;
+
+    let mut records = Records::default();
+    push_records(String::new(), value, &mut records);
+
+    pack(records, chunk_capacity)
+}
+
+/// Push the records of `value`, whose fields are named under `prefix`, descending into the objects wrapping a single array of records, or a single object.
+fn push_records(prefix: String, value: Value, records: &mut Records) {
+    let mut map = match value {
+        Value::Array(values) => {
+            for value in values {
+                records.push(flatten(&prefix, value));
+            }
+            return;
+        }
+        Value::Object(map) => map,
+        value => {
+            records.push(flatten(&prefix, value));
+            return;
+        }
+    };
+
+    let mut wrapped = map.iter().filter(|(_, value)| {
+        value
+            .as_array()
+            .is_some_and(|values| values.iter().any(|v| v.is_object() || v.is_array()))
+    });
+    let key = match (wrapped.next(), wrapped.next()) {
+        (Some((key, _)), None) => key.clone(),
+        // an object with a single object field, e.g. `{"response": {"data": [...]}}`, may wrap the records deeper
+        (None, _) if map.len() == 1 && map.values().all(Value::is_object) => {
+            map.keys().next().cloned().unwrap_or_default()
+        }
+        _ => {
+            records.push(flatten(&prefix, Value::Object(map)));
+            return;
+        }
+    };
+
+    let value = map.remove(&key).unwrap_or_default();
+    if !map.is_empty() {
+        records.push(flatten(&prefix, Value::Object(map)));
+    }
+    push_records(join(&prefix, key), value, records);
+}
+
+/// Split a json lines file into chunks of whole records, one record per line.
+pub(crate) fn chunk_jsonl(bytes: &[u8], chunk_capacity: usize) -> Result<Vec<String>, ServerError> {
+    let text = String::from_utf8_lossy(bytes);
+
+    let mut records = Records::default();
+    for (idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value: Value = serde_json::from_str(line).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to parse line {} of the json lines file. {}",
+                idx + 1,
+                e
+            ))
+        })?;
+        records.push(flatten("", value));
+    }
+
+    pack(records, chunk_capacity)
+}
+
+/// Flatten a json value into `column: value` pairs. Nested fields are named by their dotted paths, under `prefix` if it is not empty.
+fn flatten(prefix: &str, value: Value) -> Vec<(String, String)> {
+    fn walk(prefix: String, value: Value, row: &mut Vec<(String, String)>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    walk(join(&prefix, key), value, row);
+                }
+            }
+            Value::Array(values) if values.iter().all(|v| !v.is_object() && !v.is_array()) => {
+                let values = values
+                    .into_iter()
+                    .map(scalar)
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                row.push((prefix, values));
+            }
+            Value::Array(values) => {
+                for (idx, value) in values.into_iter().enumerate() {
+                    walk(format!("{}[{}]", prefix, idx), value, row);
+                }
+            }
+            value => {
+                let column = match prefix.is_empty() {
+                    true => "value".to_string(),
+                    false => prefix,
+                };
+                row.push((column, scalar(value)));
+            }
+        }
+    }
+
+    let mut row = vec![];
+    walk(prefix.to_string(), value, &mut row);
+    row
+}
+
+/// Dotted path of the field `key` under `prefix`.
+fn join(prefix: &str, key: String) -> String {
+    match prefix.is_empty() {
+        true => key,
+        false => format!("{}.{}", prefix, key),
+    }
+}
+
+fn scalar(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Pack whole records into chunks of up to `chunk_capacity` tokens. The column names are repeated at the top of every chunk, unless they take more than half of the capacity. A record larger than a chunk is split between its fields into chunks on its own, and a field larger than a chunk between its words.
+fn pack(records: Records, chunk_capacity: usize) -> Result<Vec<String>, ServerError> {
+    println!("[+] Chunking the structured data file ...");
+    println!("    * Number of records: {}", records.rows.len());
+
+    let tokenizer = super::tokenizer()?;
+
+    // the records name their columns anyway, so a header too long to repeat is left out
+    let header = format!("Columns: {}\n\n", records.columns.join(", "));
+    let header = match tokenizer.encode_ordinary(&header).len() * 2 > chunk_capacity {
+        true => String::new(),
+        false => header,
+    };
+    let header_tokens = tokenizer.encode_ordinary(&header).len();
+    // the records of a chunk are separated by a blank line
+    let capacity = chunk_capacity.saturating_sub(header_tokens + 1).max(1);
+
+    let mut chunks = vec![];
+    let mut current: Vec<String> = vec![];
+    let mut tokens = 0;
+    for row in records.rows {
+        let lines = row
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(column, value)| format!("{}: {}", column, value))
+            .collect::<Vec<String>>();
+        if lines.is_empty() {
+            continue;
+        }
+
+        let record = lines.join("\n");
+        let record_tokens = tokenizer.encode_ordinary(&record).len() + 1;
+        if !current.is_empty() && tokens + record_tokens > capacity {
+            chunks.push(format!("{}{}", header, current.join("\n\n")));
+            current.clear();
+            tokens = 0;
+        }
+
+        if record_tokens > capacity {
+            for part in split_record(&tokenizer, lines, capacity) {
+                chunks.push(format!("{}{}", header, part));
+            }
+            continue;
+        }
+
+        tokens += record_tokens;
+        current.push(record);
+    }
+    if !current.is_empty() {
+        chunks.push(format!("{}{}", header, current.join("\n\n")));
+    }
+
+    println!("    * Number of chunks: {}", chunks.len());
+
+    Ok(chunks)
+}
+
+/// Split the `column: value` lines of a record into parts of up to `capacity` tokens, between the lines, or between the words of a line larger than a part.
+fn split_record(tokenizer: &CoreBPE, lines: Vec<String>, capacity: usize) -> Vec<String> {
+    let count = |text: &str| tokenizer.encode_ordinary(text).len();
+
+    let mut pieces = vec![];
+    for line in lines {
+        match count(&line) <= capacity {
+            true => pieces.push(line),
+            false => pieces.extend(split_words(tokenizer, &line, capacity)),
+        }
+    }
+
+    let mut parts = vec![];
+    let mut current: Vec<String> = vec![];
+    let mut tokens = 0;
+    for piece in pieces {
+        // the pieces of a part are separated by a line break
+        let piece_tokens = count(&piece) + 1;
+        if !current.is_empty() && tokens + piece_tokens > capacity {
+            parts.push(current.join("\n"));
+            current.clear();
+            tokens = 0;
+        }
+        tokens += piece_tokens;
+        current.push(piece);
+    }
+    if !current.is_empty() {
+        parts.push(current.join("\n"));
+    }
+
+    parts
+}
+
+/// Split a line into pieces of up to `capacity` tokens between its words. A word larger than a piece is cut between its characters.
+fn split_words(tokenizer: &CoreBPE, line: &str, capacity: usize) -> Vec<String> {
+    let count = |text: &str| tokenizer.encode_ordinary(text).len();
+    // a token holds at least one byte, so a character makes at most 4 tokens
+    let max_chars = (capacity.saturating_sub(1) / 4).max(1);
+
+    let mut words = vec![];
+    for word in line.split_whitespace() {
+        match count(word) < capacity {
+            true => words.push(word.to_string()),
+            false => {
+                let chars = word.chars().collect::<Vec<char>>();
+                words.extend(
+                    chars
+                        .chunks(max_chars)
+                        .map(|c| c.iter().collect::<String>()),
+                );
+            }
+        }
+    }
+
+    // the words are counted apart, as the tokenizer never merges tokens across spaces
+    let mut pieces = vec![];
+    let mut current = String::new();
+    let mut tokens = 0;
+    for word in words {
+        let word_tokens = count(&format!(" {}", word));
+        if !current.is_empty() && tokens + word_tokens > capacity {
+            pieces.push(std::mem::take(&mut current));
+            tokens = 0;
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(&word);
+        tokens += word_tokens;
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_tokens(chunks: &[String]) -> usize {
+        let tokenizer = super::super::tokenizer().unwrap();
+        chunks
+            .iter()
+            .map(|chunk| tokenizer.encode_ordinary(chunk).len())
+            .max()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn json_array_of_records() {
+        let json =
+            br#"[{"name": "Paris", "country": "France"}, {"name": "Rome", "country": "Italy"}]"#;
+
+        let chunks = chunk_json(json, 100).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].starts_with("Columns: country, name\n\n"));
+        assert!(chunks[0].contains("country: France\nname: Paris\n\ncountry: Italy"));
+    }
+
+    #[test]
+    fn json_object_wrapping_records() {
+        let json = br#"{"total": 2, "data": [{"name": "Paris"}, {"name": "Rome"}]}"#;
+
+        let chunks = chunk_json(json, 100).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].contains("total: 2"));
+        assert!(chunks[0].contains("data.name: Paris\n\ndata.name: Rome"));
+    }
+
+    #[test]
+    fn json_nested_wrappers() {
+        let json = br#"{"response": {"items": [{"name": "Paris"}, {"name": "Rome"}]}}"#;
+
+        let chunks = chunk_json(json, 100).unwrap();
+        assert!(chunks[0].contains("response.items.name: Paris"));
+        assert!(!chunks[0].contains("[0]"));
+    }
+
+    #[test]
+    fn json_wrapped_records_split_across_chunks() {
+        let records = (0..200)
+            .map(|i| format!(r#"{{"id": {}, "name": "city number {}"}}"#, i, i))
+            .collect::<Vec<String>>()
+            .join(",");
+        let json = format!(r#"{{"data": [{}]}}"#, records);
+
+        let chunks = chunk_json(json.as_bytes(), 64).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(max_tokens(&chunks) <= 64);
+    }
+
+    #[test]
+    fn record_larger_than_chunk_split_by_field() {
+        let fields = (0..50)
+            .map(|i| format!(r#""field_{}": "value of the field number {}""#, i, i))
+            .collect::<Vec<String>>()
+            .join(",");
+        let json = format!("{{{}}}", fields);
+
+        let chunks = chunk_json(json.as_bytes(), 64).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(max_tokens(&chunks) <= 64);
+        assert!(chunks.iter().any(|chunk| chunk.contains("field_49: ")));
+    }
+
+    #[test]
+    fn field_larger_than_chunk_split_by_word() {
+        let text = "word ".repeat(1000) + &"x".repeat(500);
+        let json = serde_json::json!([{ "text": text }]).to_string();
+
+        let chunks = chunk_json(json.as_bytes(), 50).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(max_tokens(&chunks) <= 50);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let csv = b"name,country\nParis,France\nRome,Italy\n";
+
+        let chunks = chunk_csv(csv, 100).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].contains("name: Rome\ncountry: Italy"));
+    }
+}
@@ -1,6 +1,12 @@
 mod backend;
+mod collections;
 mod error;
+mod ingest;
+mod registry;
+mod retrieval;
 mod utils;
+mod vector_store;
+mod watch;
 
 use anyhow::Result;
 use chat_prompts::{MergeRagContextPolicy, PromptTemplateType};
@@ -13,9 +19,11 @@ use hyper::{
 };
 use llama_core::MetadataBuilder;
 use once_cell::sync::OnceCell;
+use retrieval::RagStrategy;
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 use utils::{is_valid_url, log};
+use vector_store::{EmbeddedStore, QdrantStore, RetrievalMode, Store, VectorStoreKind};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -23,9 +31,17 @@ type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 pub(crate) static GLOBAL_RAG_PROMPT: OnceCell<String> = OnceCell::new();
 // server info
 pub(crate) static SERVER_INFO: OnceCell<ServerInfo> = OnceCell::new();
+// vector store
+pub(crate) static VECTOR_STORE: OnceCell<Store> = OnceCell::new();
+// directory of the keyword indexes
+pub(crate) static KEYWORD_INDEX_DIR: OnceCell<PathBuf> = OnceCell::new();
+// hosts documents may be downloaded from even if their addresses are not public
+pub(crate) static DOWNLOAD_ALLOWED_HOSTS: OnceCell<Vec<String>> = OnceCell::new();
 
 // default socket address
 const DEFAULT_SOCKET_ADDRESS: &str = "0.0.0.0:8080";
+/// Default upper bound of the max number of retrieved result per request, raised to `--qdrant-limit` if lower
+const DEFAULT_MAX_LIMIT: u64 = 20;
 
 #[derive(Clone, Debug)]
 pub struct AppState {
@@ -70,21 +86,69 @@ struct Cli {
     /// Strategy for merging RAG context into chat messages.
     #[arg(long = "rag-policy", default_value_t, value_enum)]
     policy: MergeRagContextPolicy,
+    /// Margin added to the token counts of the retrieved context, in percent. The tokens are counted with the `cl100k_base` encoding, so the margin covers a chat model tokenizer splitting text into up to that many percent more tokens
+    #[arg(long, default_value = "25", value_parser = clap::value_parser!(u64))]
+    context_token_margin: u64,
+    /// Vector store persisting the embeddings of the document chunks
+    #[arg(long, default_value_t, value_enum)]
+    vector_store: VectorStoreKind,
+    /// Directory of the embedded vector store
+    #[arg(long, default_value = "vector_store")]
+    vector_store_dir: PathBuf,
+    /// Directory of the BM25 keyword indexes [default: `keyword_index` in `--vector-store-dir` with the embedded vector store, `keyword_index` otherwise]
+    #[arg(long)]
+    keyword_index_dir: Option<PathBuf>,
     /// URL of Qdrant REST Service
     #[arg(long, default_value = "http://localhost:6333")]
     qdrant_url: String,
     /// Name of Qdrant collection
     #[arg(long, default_value = "default")]
     qdrant_collection_name: String,
+    /// Path to the YAML file configuring several named collections
+    #[arg(long)]
+    collections_config: Option<PathBuf>,
     /// Max number of retrieved result (no less than 1)
     #[arg(long, default_value = "5", value_parser = clap::value_parser!(u64))]
     qdrant_limit: u64,
+    /// Upper bound of the max number of retrieved result set by requests with `rag_limit`, and of the rerank candidates [default: the greater of 20 and `--qdrant-limit`]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    qdrant_max_limit: Option<u64>,
     /// Minimal score threshold for the search result
     #[arg(long, default_value = "0.4", value_parser = clap::value_parser!(f32))]
     qdrant_score_threshold: f32,
+    /// How the points are retrieved: by vector similarity, by BM25 keyword score, or both fused by reciprocal rank
+    #[arg(long, default_value_t, value_enum)]
+    retrieval_mode: RetrievalMode,
+    /// Rewrite the last user message into a standalone query with the chat model, using the earlier messages, before the retrieval
+    #[arg(long)]
+    rewrite_query: bool,
+    /// Queries the points are retrieved for: the user query only, along with paraphrases of it, or along with a hypothetical answer to it (HyDE), all generated by the chat model
+    #[arg(long, default_value_t, value_enum)]
+    rag_strategy: RagStrategy,
+    /// Rerank the retrieved points by asking the chat model for their relevance to the query
+    #[arg(long)]
+    rerank: bool,
+    /// Number of points retrieved for the reranking or the MMR selection, of which `--qdrant-limit` are kept. Capped to `--qdrant-max-limit`
+    #[arg(long, default_value = "20", value_parser = clap::value_parser!(u64).range(1..))]
+    rerank_candidates: u64,
+    /// Select the retrieved points by maximal marginal relevance, so that they are not near duplicates of each other
+    #[arg(long)]
+    mmr: bool,
+    /// Weight of the relevance against the diversity in the MMR selection, from 0 (diversity only) to 1 (relevance only)
+    #[arg(long, default_value = "0.5", value_parser = clap::value_parser!(f32))]
+    mmr_lambda: f32,
     /// Maximum number of tokens each chunk contains
     #[arg(long, default_value = "100", value_parser = clap::value_parser!(usize))]
     chunk_capacity: usize,
+    /// Hosts `/v1/create/rag/url` may download documents from even if they resolve to loopback, link-local or private addresses. The hosts are separated by comma without space, for example, '--download-allowed-hosts localhost,10.0.0.5'.
+    #[arg(long, value_delimiter = ',')]
+    download_allowed_hosts: Vec<String>,
+    /// Directory to index at startup and keep in sync with the Qdrant collection
+    #[arg(long)]
+    watch_dir: Option<PathBuf>,
+    /// Interval in seconds between two scans of the watched directory
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    watch_interval: u64,
     /// Print prompt strings to stdout
     #[arg(long)]
     log_prompts: bool,
@@ -159,13 +223,43 @@ async fn main() -> Result<(), ServerError> {
         })?;
     }
 
-    if !is_valid_url(&cli.qdrant_url) {
-        return Err(ServerError::ArgumentError(format!(
-            "The URL of Qdrant REST API is invalid: {}.",
-            &cli.qdrant_url
-        )));
-    }
-    log(format!("[INFO] Qdrant server url: {}", &cli.qdrant_url));
+    log(format!("[INFO] Vector store: {}", cli.vector_store));
+    let store = match cli.vector_store {
+        VectorStoreKind::Qdrant => {
+            if !is_valid_url(&cli.qdrant_url) {
+                return Err(ServerError::ArgumentError(format!(
+                    "The URL of Qdrant REST API is invalid: {}.",
+                    &cli.qdrant_url
+                )));
+            }
+            log(format!("[INFO] Qdrant server url: {}", &cli.qdrant_url));
+
+            Store::Qdrant(QdrantStore::new(&cli.qdrant_url))
+        }
+        VectorStoreKind::Embedded => {
+            log(format!(
+                "[INFO] Vector store directory: {}",
+                cli.vector_store_dir.display()
+            ));
+
+            Store::Embedded(EmbeddedStore::new(&cli.vector_store_dir)?)
+        }
+    };
+    VECTOR_STORE
+        .set(store)
+        .map_err(|_| ServerError::Operation("Failed to set `VECTOR_STORE`.".to_string()))?;
+    let keyword_index_dir = match (&cli.keyword_index_dir, cli.vector_store) {
+        (Some(dir), _) => dir.clone(),
+        (None, VectorStoreKind::Embedded) => cli.vector_store_dir.join("keyword_index"),
+        (None, VectorStoreKind::Qdrant) => PathBuf::from("keyword_index"),
+    };
+    log(format!(
+        "[INFO] Keyword index directory: {}",
+        keyword_index_dir.display()
+    ));
+    KEYWORD_INDEX_DIR
+        .set(keyword_index_dir)
+        .map_err(|_| ServerError::Operation("Failed to set `KEYWORD_INDEX_DIR`.".to_string()))?;
     log(format!(
         "[INFO] Qdrant collection name: {}",
         &cli.qdrant_collection_name
@@ -174,21 +268,128 @@ async fn main() -> Result<(), ServerError> {
         "[INFO] Max number of retrieved result: {}",
         &cli.qdrant_limit
     ));
+    let max_limit = match cli.qdrant_max_limit {
+        Some(max_limit) if cli.qdrant_limit > max_limit => {
+            return Err(ServerError::ArgumentError(format!(
+                "The max number of retrieved result ({}) exceeds `--qdrant-max-limit` ({}).",
+                cli.qdrant_limit, max_limit
+            )));
+        }
+        Some(max_limit) => max_limit,
+        None => cli.qdrant_limit.max(DEFAULT_MAX_LIMIT),
+    };
+    log(format!(
+        "[INFO] Upper bound of the max number of retrieved result per request: {}",
+        &max_limit
+    ));
     log(format!(
         "[INFO] Qdrant score threshold: {}",
         &cli.qdrant_score_threshold
     ));
+    log(format!("[INFO] Retrieval mode: {}", &cli.retrieval_mode));
+    log(format!("[INFO] Rewrite query: {}", &cli.rewrite_query));
+    log(format!("[INFO] RAG strategy: {}", &cli.rag_strategy));
+    log(format!("[INFO] Rerank: {}", &cli.rerank));
+    log(format!(
+        "[INFO] Rerank candidates: {}",
+        &cli.rerank_candidates
+    ));
+    if cli.rerank_candidates > max_limit {
+        log(format!(
+            "[WARNING] The rerank candidates are capped to `--qdrant-max-limit` ({}).",
+            max_limit
+        ));
+    }
+    log(format!("[INFO] MMR: {}", &cli.mmr));
+    if !(0.0..=1.0).contains(&cli.mmr_lambda) {
+        return Err(ServerError::ArgumentError(format!(
+            "`--mmr-lambda` must be in [0, 1], but it is {}.",
+            cli.mmr_lambda
+        )));
+    }
+    log(format!("[INFO] MMR lambda: {}", &cli.mmr_lambda));
+    let mut collection_name = cli.qdrant_collection_name;
+    let mut collections = vec![];
+    if let Some(path) = &cli.collections_config {
+        let (default, configured) = collections::load(path)?;
+        if let Some(default) = default {
+            collection_name = default;
+        }
+        collections = configured;
+
+        // the default collection is always available
+        if !collections.iter().any(|c| c.name == collection_name) {
+            collections.push(collections::CollectionConfig {
+                name: collection_name.clone(),
+                ..Default::default()
+            });
+        }
+
+        log(format!("[INFO] Collections config: {}", path.display()));
+        log(format!("[INFO] Default collection: {}", &collection_name));
+        for collection in collections.iter() {
+            if collection.limit.unwrap_or(cli.qdrant_limit) > max_limit {
+                return Err(ServerError::ArgumentError(format!(
+                    "The limit of the collection {} exceeds `--qdrant-max-limit` ({}).",
+                    &collection.name, max_limit
+                )));
+            }
+
+            match collection.models.is_empty() {
+                true => log(format!("[INFO] Collection: {}", &collection.name)),
+                false => log(format!(
+                    "[INFO] Collection: {} (models: {})",
+                    &collection.name,
+                    collection.models.join(",")
+                )),
+            }
+        }
+    }
     let qdrant_config = QdrantConfig {
+        vector_store: cli.vector_store,
         url: cli.qdrant_url,
-        collection_name: cli.qdrant_collection_name,
+        collection_name,
         limit: cli.qdrant_limit,
+        max_limit,
         score_threshold: cli.qdrant_score_threshold,
+        retrieval_mode: cli.retrieval_mode,
+        rewrite_query: cli.rewrite_query,
+        strategy: cli.rag_strategy,
+        rerank: cli.rerank,
+        rerank_candidates: cli.rerank_candidates,
+        mmr: cli.mmr,
+        mmr_lambda: cli.mmr_lambda,
+        collections,
     };
 
     log(format!(
         "[INFO] Chunk capacity (in tokens): {}",
         &cli.chunk_capacity
     ));
+    if !cli.download_allowed_hosts.is_empty() {
+        log(format!(
+            "[INFO] Hosts allowed for downloads: {}",
+            cli.download_allowed_hosts.join(",")
+        ));
+    }
+    DOWNLOAD_ALLOWED_HOSTS
+        .set(cli.download_allowed_hosts.clone())
+        .map_err(|_| {
+            ServerError::Operation("Failed to set `DOWNLOAD_ALLOWED_HOSTS`.".to_string())
+        })?;
+    if let Some(watch_dir) = &cli.watch_dir {
+        if !watch_dir.is_dir() {
+            return Err(ServerError::ArgumentError(format!(
+                "The watched directory does not exist: {}.",
+                watch_dir.display()
+            )));
+        }
+        log(format!("[INFO] Watched directory: {}", watch_dir.display()));
+        log(format!(
+            "[INFO] Watch interval (in seconds): {}",
+            &cli.watch_interval
+        ));
+    }
     log(format!("[INFO] Enable prompt log: {}", &cli.log_prompts));
     log(format!("[INFO] Enable plugin log: {}", &cli.log_stat));
     log(format!("[INFO] Socket address: {}", &cli.socket_addr));
@@ -201,6 +402,10 @@ async fn main() -> Result<(), ServerError> {
         policy = MergeRagContextPolicy::LastUserMessage;
         log(format!("       * Updated RAG policy: {}", policy));
     }
+    log(format!(
+        "[INFO] Context token margin (in percent): {}",
+        &cli.context_token_margin
+    ));
 
     // create metadata for chat model
     let chat_metadata = MetadataBuilder::new(
@@ -270,6 +475,7 @@ async fn main() -> Result<(), ServerError> {
         chat_model: chat_model_info,
         embedding_model: embedding_model_info,
         policy: cli.policy,
+        context_token_margin: cli.context_token_margin,
     };
 
     // initialize the core context
@@ -308,6 +514,10 @@ async fn main() -> Result<(), ServerError> {
         .set(server_info)
         .map_err(|_| ServerError::Operation("Failed to set `SERVER_INFO`.".to_string()))?;
 
+    let watch_dir = cli.watch_dir.clone();
+    let chunk_capacity = cli.chunk_capacity;
+    let watch_interval = Duration::from_secs(cli.watch_interval);
+
     let new_service = make_service_fn(move |_| {
         let web_ui = cli.web_ui.to_string_lossy().to_string();
         let chunk_capacity = cli.chunk_capacity;
@@ -325,6 +535,14 @@ async fn main() -> Result<(), ServerError> {
         addr.port()
     ));
 
+    // the watcher runs as a task of its own, so that the server keeps serving if it stops or panics
+    if let Some(dir) = watch_dir {
+        tokio::spawn(async move {
+            watch::watch(dir, watch_interval, chunk_capacity).await;
+            log("[ERROR] The watcher of the watched directory stopped.".to_string());
+        });
+    }
+
     match server.await {
         Ok(_) => Ok(()),
         Err(e) => Err(ServerError::Operation(e.to_string())),
@@ -377,10 +595,38 @@ fn static_response(path_str: &str, root: String) -> Response<Body> {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub(crate) struct QdrantConfig {
+    /// Vector store backend. The `url` is that of the Qdrant server.
+    pub(crate) vector_store: VectorStoreKind,
     pub(crate) url: String,
     pub(crate) collection_name: String,
     pub(crate) limit: u64,
+    /// Upper bound of the limit set by requests
+    pub(crate) max_limit: u64,
     pub(crate) score_threshold: f32,
+    /// Default retrieval mode of the requests
+    #[serde(default)]
+    pub(crate) retrieval_mode: RetrievalMode,
+    /// Whether the last user message is rewritten into a standalone query by default
+    #[serde(default)]
+    pub(crate) rewrite_query: bool,
+    /// Default queries the points are retrieved for
+    #[serde(default)]
+    pub(crate) strategy: RagStrategy,
+    /// Whether the retrieved points are reranked by the chat model by default
+    #[serde(default)]
+    pub(crate) rerank: bool,
+    /// Number of points retrieved for the reranking or the MMR selection
+    #[serde(default)]
+    pub(crate) rerank_candidates: u64,
+    /// Whether the retrieved points are selected by maximal marginal relevance by default
+    #[serde(default)]
+    pub(crate) mmr: bool,
+    /// Weight of the relevance against the diversity in the MMR selection
+    #[serde(default)]
+    pub(crate) mmr_lambda: f32,
+    /// Named collections of the collections config. Empty without a config.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) collections: Vec<collections::CollectionConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -419,4 +665,6 @@ pub(crate) struct RagConfig {
     pub chat_model: ModelConfig,
     pub embedding_model: ModelConfig,
     pub policy: MergeRagContextPolicy,
+    /// Margin added to the token counts of the retrieved context, in percent
+    pub context_token_margin: u64,
 }
@@ -0,0 +1,279 @@
+//! Persistent metadata of the archived files, kept in memory and in `archives.json` next to the `archives` directory.
+
+use crate::{error::ServerError, utils::walk_dir};
+use endpoints::files::FileObject;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+/// Path of the registry file.
+const REGISTRY_FILE: &str = "archives.json";
+
+/// Records of the archived files, loaded from the registry file on first use. Every change is written through to the file.
+static RECORDS: Lazy<Mutex<Option<Vec<FileRecord>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Metadata of an archived file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FileRecord {
+    /// Id of the archive directory the file is stored in
+    pub(crate) id: String,
+    pub(crate) object: String,
+    /// Size of the file in bytes
+    pub(crate) bytes: u64,
+    /// Unix timestamp (in seconds) of the upload
+    pub(crate) created_at: u64,
+    /// Path of the file in its archive directory
+    pub(crate) filename: String,
+    pub(crate) purpose: String,
+    /// SHA-256 checksum of the contents, in hex
+    pub(crate) checksum: String,
+    /// Number of chunks embedded. `None` if the file is not embedded yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chunks: Option<usize>,
+    /// Model that computed the embeddings of the chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) embedding_model: Option<String>,
+    /// Qdrant collection the chunks are persisted in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) collection: Option<String>,
+    /// Key/value tags set on upload
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: BTreeMap<String, String>,
+}
+impl FileRecord {
+    pub(crate) fn new(file_object: &FileObject, checksum: String) -> Self {
+        Self {
+            id: file_object.id.clone(),
+            object: "file".to_string(),
+            bytes: file_object.bytes,
+            created_at: file_object.created_at,
+            filename: file_object.filename.clone(),
+            purpose: file_object.purpose.clone(),
+            checksum,
+            chunks: None,
+            embedding_model: None,
+            collection: None,
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+/// Compute the SHA-256 checksum of the contents of a file.
+pub(crate) fn checksum(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        })
+}
+
+/// List the records of all archived files, or only those of the given id.
+pub(crate) fn list(id: Option<&str>) -> Result<Vec<FileRecord>, ServerError> {
+    with_records(|records| {
+        records
+            .iter()
+            .filter(|record| id.is_none_or(|id| record.id == id))
+            .cloned()
+            .collect()
+    })
+}
+
+/// Add the record of a newly archived file. A previous record of the same file is replaced.
+pub(crate) fn add(record: FileRecord) -> Result<(), ServerError> {
+    update(|records| {
+        records.retain(|r| !(r.id == record.id && r.filename == record.filename));
+        records.push(record);
+        1
+    })?;
+
+    Ok(())
+}
+
+/// Record that the chunks of an archived file are embedded. Files without a record, e.g. watched documents, are ignored.
+pub(crate) fn set_embedded(
+    id: &str,
+    filename: &str,
+    chunks: usize,
+    embedding_model: &str,
+    collection: &str,
+) -> Result<(), ServerError> {
+    update(|records| {
+        match records
+            .iter_mut()
+            .find(|record| record.id == id && record.filename == filename)
+        {
+            Some(record) => {
+                record.chunks = Some(chunks);
+                record.embedding_model = Some(embedding_model.to_string());
+                record.collection = Some(collection.to_string());
+                1
+            }
+            None => 0,
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Find an archived file with the given checksum whose chunks are embedded into `collection`.
+pub(crate) fn find_embedded(
+    checksum: &str,
+    collection: &str,
+) -> Result<Option<FileRecord>, ServerError> {
+    with_records(|records| {
+        records
+            .iter()
+            .find(|record| {
+                record.checksum == checksum
+                    && record.chunks.is_some()
+                    && record.collection.as_deref() == Some(collection)
+            })
+            .cloned()
+    })
+}
+
+/// Record that the chunks embedded into `collection` are gone, e.g. once the collection is dropped. Returns the number of records updated.
+pub(crate) fn clear_collection(collection: &str) -> Result<usize, ServerError> {
+    update(|records| {
+        let mut count = 0;
+        for record in records
+            .iter_mut()
+            .filter(|record| record.collection.as_deref() == Some(collection))
+        {
+            record.chunks = None;
+            record.embedding_model = None;
+            record.collection = None;
+            count += 1;
+        }
+        count
+    })
+}
+
+/// Remove the records of all files of the given id, or only the record of `filename` if it is set.
+pub(crate) fn remove(id: &str, filename: Option<&str>) -> Result<(), ServerError> {
+    update(|records| {
+        let len = records.len();
+        records.retain(|record| {
+            record.id != id || filename.is_some_and(|filename| record.filename != filename)
+        });
+        len - records.len()
+    })?;
+
+    Ok(())
+}
+
+fn lock() -> Result<MutexGuard<'static, Option<Vec<FileRecord>>>, ServerError> {
+    RECORDS
+        .lock()
+        .map_err(|_| ServerError::Operation("The registry is poisoned.".to_string()))
+}
+
+/// Run `f` on the records, loaded on first use.
+fn with_records<T>(f: impl FnOnce(&Vec<FileRecord>) -> T) -> Result<T, ServerError> {
+    let mut records = lock()?;
+    if records.is_none() {
+        *records = Some(load()?);
+    }
+
+    Ok(f(records.get_or_insert_with(Vec::new)))
+}
+
+/// Apply `f` to the records and persist them. `f` returns the number of records it changed, and nothing is written if none is. The records are left as they were if they fail to be persisted.
+fn update(f: impl FnOnce(&mut Vec<FileRecord>) -> usize) -> Result<usize, ServerError> {
+    let mut records = lock()?;
+    if records.is_none() {
+        *records = Some(load()?);
+    }
+
+    let mut updated = records.clone().unwrap_or_default();
+    let count = f(&mut updated);
+    if count > 0 {
+        save(&updated)?;
+        *records = Some(updated);
+    }
+
+    Ok(count)
+}
+
+fn load() -> Result<Vec<FileRecord>, ServerError> {
+    let path = Path::new(REGISTRY_FILE);
+    if !path.exists() {
+        // the files archived before the registry existed are recorded along with the first change
+        return Ok(scan_archives());
+    }
+
+    let json = fs::read_to_string(path)
+        .map_err(|e| ServerError::Operation(format!("Failed to read {}. {}", REGISTRY_FILE, e)))?;
+
+    serde_json::from_str(&json)
+        .map_err(|e| ServerError::Operation(format!("Failed to parse {}. {}", REGISTRY_FILE, e)))
+}
+
+fn save(records: &[FileRecord]) -> Result<(), ServerError> {
+    let json = serde_json::to_string_pretty(records).map_err(|e| {
+        ServerError::Operation(format!("Failed to serialize {}. {}", REGISTRY_FILE, e))
+    })?;
+
+    // replace the registry at once, so that it is never left half written
+    let tmp = format!("{}.tmp", REGISTRY_FILE);
+    fs::write(&tmp, json)
+        .and_then(|_| fs::rename(&tmp, REGISTRY_FILE))
+        .map_err(|e| ServerError::Operation(format!("Failed to write {}. {}", REGISTRY_FILE, e)))
+}
+
+/// Build the records of the files found under `archives`.
+fn scan_archives() -> Vec<FileRecord> {
+    let path = Path::new("archives");
+
+    let ids = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    let mut records = vec![];
+    for id in ids {
+        for (filename, metadata) in walk_dir(&path.join(&id)) {
+            let bytes = match fs::read(path.join(&id).join(&filename)) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+
+            let created_at = metadata
+                .created()
+                .or_else(|_| metadata.modified())
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+
+            records.push(FileRecord {
+                id: id.clone(),
+                object: "file".to_string(),
+                bytes: metadata.len(),
+                created_at,
+                filename,
+                purpose: "assistants".to_string(),
+                checksum: checksum(&bytes),
+                chunks: None,
+                embedding_model: None,
+                collection: None,
+                tags: BTreeMap::new(),
+            });
+        }
+    }
+    records.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+
+    records
+}
@@ -0,0 +1,244 @@
+//! Fit the retrieved chunks in the context window of the chat model, along with the prompt and the answer.
+//!
+//! `llama-core` does not expose the tokenizer of the chat model, so the tokens are counted with the encoding measuring the chunks, plus `--context-token-margin` percent for the tokenizers splitting text into more tokens. The margin is an assumption on the chat model, not a bound measured on its vocabulary.
+
+use super::citations::numbered;
+use crate::{error::ServerError, ingest, vector_store::ScoredChunk};
+use serde::Serialize;
+use tiktoken_rs::CoreBPE;
+
+/// Min number of tokens of a truncated chunk. A chunk which would be cut shorter is dropped.
+const MIN_TRUNCATED_TOKENS: usize = 32;
+
+/// How the retrieved chunks fit in the context window.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ContextReport {
+    /// Max number of tokens of the context
+    pub(crate) budget: usize,
+    /// Number of tokens of the context
+    pub(crate) tokens: usize,
+    /// Number of chunks in the context
+    pub(crate) chunks: usize,
+    /// Chunk cut to fit in the context, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) truncated: Option<TrimmedChunk>,
+    /// Chunks left out of the context, best first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) dropped: Vec<TrimmedChunk>,
+}
+
+/// A chunk cut or left out to fit in the context.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TrimmedChunk {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) file_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) chunk_index: Option<usize>,
+    pub(crate) score: f32,
+    /// Number of tokens of the whole chunk
+    pub(crate) tokens: usize,
+}
+impl TrimmedChunk {
+    fn new(point: &ScoredChunk, tokens: usize) -> Self {
+        Self {
+            file_id: point.meta.file_id.clone(),
+            filename: point.meta.filename.clone(),
+            chunk_index: point.meta.chunk_index,
+            score: point.score,
+            tokens,
+        }
+    }
+}
+
+/// Keep the chunks, ranked best first, that fit in the context window of `ctx_size` tokens along with the `prompt` and an answer of `answer_tokens` tokens. The `prompt` is the prompt of the chat model without the chunks, i.e. the conversation in the prompt template along with the RAG prompt. The tokens are counted with `margin` percent more.
+///
+/// The best chunks are kept whole. The first chunk that does not fit is cut to the tokens left, and the following ones are dropped.
+pub(crate) fn fit_context(
+    points: Vec<ScoredChunk>,
+    prompt: &str,
+    ctx_size: u64,
+    answer_tokens: u64,
+    margin: u64,
+) -> Result<(Vec<ScoredChunk>, ContextReport), ServerError> {
+    let bpe = ingest::tokenizer()?;
+
+    // llama-core drops the earliest turns of prompts longer than 4/5 of the context window
+    let max_prompt_tokens = (ctx_size * 4 / 5).min(ctx_size.saturating_sub(answer_tokens)) as usize;
+    let prompt_tokens = count_tokens(&bpe, prompt, margin);
+    let budget = max_prompt_tokens.saturating_sub(prompt_tokens);
+
+    println!("\n[+] Fitting the context in the context window ...");
+    println!("    * context window: {}", ctx_size);
+    println!("    * answer tokens: {}", answer_tokens);
+    println!("    * prompt tokens: {}", prompt_tokens);
+    println!("    * context budget: {}", budget);
+
+    let mut report = ContextReport {
+        budget,
+        tokens: 0,
+        chunks: 0,
+        truncated: None,
+        dropped: vec![],
+    };
+    let mut kept = vec![];
+    for mut point in points {
+        // the chunks are numbered in the context in the order they are kept
+        let id = kept.len() + 1;
+        let tokens = count_tokens(&bpe, &numbered(id, &point.source), margin);
+        let left = budget.saturating_sub(report.tokens);
+
+        if report.dropped.is_empty() && report.truncated.is_none() {
+            if tokens <= left {
+                report.tokens += tokens;
+                kept.push(point);
+                continue;
+            }
+
+            if left >= MIN_TRUNCATED_TOKENS {
+                if let Some((text, truncated_tokens)) =
+                    truncate(&bpe, &point.source, id, left, margin)
+                {
+                    println!(
+                        "    * truncated chunk: {} tokens of {}",
+                        truncated_tokens, tokens
+                    );
+                    report.truncated = Some(TrimmedChunk::new(&point, tokens));
+                    report.tokens += truncated_tokens;
+                    point.source = text;
+                    kept.push(point);
+                    continue;
+                }
+            }
+        }
+
+        report.dropped.push(TrimmedChunk::new(&point, tokens));
+    }
+    report.chunks = kept.len();
+
+    println!("    * context tokens: {}", report.tokens);
+    if !report.dropped.is_empty() {
+        println!("    * dropped chunks: {}", report.dropped.len());
+    }
+
+    Ok((kept, report))
+}
+
+/// Estimate the number of tokens of the text for the chat model: the tokens of the encoding, plus `margin` percent.
+fn count_tokens(bpe: &CoreBPE, text: &str, margin: u64) -> usize {
+    let tokens = bpe.encode_ordinary(text).len();
+    tokens + (tokens * margin as usize).div_ceil(100)
+}
+
+/// Cut the text so that it counts at most `max_tokens` tokens as the chunk number `id` of the context. Returns the cut text along with its tokens, or `None` if no cut fits.
+fn truncate(
+    bpe: &CoreBPE,
+    text: &str,
+    id: usize,
+    max_tokens: usize,
+    margin: u64,
+) -> Option<(String, usize)> {
+    let tokens = bpe.encode_ordinary(text.trim());
+
+    let mut end = (max_tokens * 100 / (100 + margin as usize)).min(tokens.len());
+    while end > 0 {
+        // a cut in the middle of a character is not valid UTF-8
+        let cut = match bpe.decode(tokens[..end].to_vec()) {
+            Ok(cut) => cut,
+            Err(_) => {
+                end -= 1;
+                continue;
+            }
+        };
+
+        // the cut text may count more tokens once encoded again, along with its number
+        let cut_tokens = count_tokens(bpe, &numbered(id, &cut), margin);
+        match cut_tokens <= max_tokens {
+            true => return Some((cut, cut_tokens)),
+            false => end = end.saturating_sub(cut_tokens - max_tokens),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(text: &str) -> ScoredChunk {
+        ScoredChunk {
+            source: text.to_string(),
+            score: 0.5,
+            meta: Default::default(),
+            id: None,
+            vector: vec![],
+        }
+    }
+
+    #[test]
+    fn count_with_margin() {
+        let bpe = ingest::tokenizer().unwrap();
+        let text = "The quick brown fox jumps over the lazy dog.";
+        let tokens = bpe.encode_ordinary(text).len();
+
+        assert_eq!(count_tokens(&bpe, text, 0), tokens);
+        assert_eq!(count_tokens(&bpe, text, 100), 2 * tokens);
+        // the margin is rounded up
+        assert_eq!(count_tokens(&bpe, "fox", 25), 2);
+    }
+
+    #[test]
+    fn truncate_within_tokens() {
+        let bpe = ingest::tokenizer().unwrap();
+        let text = "The disk quota is exceeded on the volume. ".repeat(20);
+
+        let (cut, tokens) = truncate(&bpe, &text, 1, 40, 25).unwrap();
+        assert!(text.starts_with(&cut));
+        assert!(tokens <= 40);
+        assert_eq!(tokens, count_tokens(&bpe, &numbered(1, &cut), 25));
+        assert!(truncate(&bpe, &text, 1, 3, 25).is_none());
+    }
+
+    #[test]
+    fn truncate_between_characters() {
+        let bpe = ingest::tokenizer().unwrap();
+        // characters of several tokens each, which a cut may split
+        let text = "🦀🐘🦩🦔🦦🦥".repeat(10);
+
+        for max_tokens in 8..40 {
+            if let Some((cut, tokens)) = truncate(&bpe, &text, 1, max_tokens, 25) {
+                assert!(text.starts_with(&cut));
+                assert!(tokens <= max_tokens);
+            }
+        }
+        assert!(truncate(&bpe, &text, 1, 39, 25).is_some());
+    }
+
+    #[test]
+    fn fit_in_budget() {
+        let bpe = ingest::tokenizer().unwrap();
+        let chunk = "The disk quota is exceeded on the volume. ".repeat(10);
+        let chunk_tokens = count_tokens(&bpe, &numbered(1, &chunk), 25);
+        let prompt = "Answer the question with the context.";
+        let prompt_tokens = count_tokens(&bpe, prompt, 25);
+        // room for two chunks and a half, with the answer
+        let ctx_size = (prompt_tokens + chunk_tokens * 5 / 2 + 1000) as u64;
+
+        let (kept, report) = fit_context(
+            vec![point(&chunk), point(&chunk), point(&chunk), point(&chunk)],
+            prompt,
+            ctx_size,
+            1000,
+            25,
+        )
+        .unwrap();
+        assert_eq!(report.budget, ctx_size as usize - 1000 - prompt_tokens);
+        assert_eq!((kept.len(), report.chunks), (3, 3));
+        assert!(report.truncated.is_some());
+        assert_eq!(report.dropped.len(), 1);
+        assert!(report.tokens <= report.budget);
+        assert!(kept[2].source.len() < chunk.len());
+    }
+}
@@ -0,0 +1,56 @@
+//! Number the chunks of the context, so that the answer can cite them by number, and list the sources the numbers stand for.
+
+use crate::vector_store::ScoredChunk;
+use serde::Serialize;
+
+/// Instructions to cite the chunks, following the numbered chunks in the context
+pub(crate) const CITATION_PROMPT: &str = "Cite the pieces of context the answer is based on by their numbers in square brackets, e.g. [1] or [1][3].";
+
+/// A chunk of the context, cited as `[id]` in the answer.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Citation {
+    /// Number of the chunk in the context, starting from 1
+    pub(crate) id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) file_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) chunk_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) page: Option<u32>,
+    /// Text of the chunk, as given to the model
+    pub(crate) text: String,
+    pub(crate) score: f32,
+}
+
+/// The chunk `text` numbered `id` in the context.
+pub(super) fn numbered(id: usize, text: &str) -> String {
+    format!("[{}] {}\n\n", id, text.trim())
+}
+
+/// Build the context of the chunks, ranked best first, each preceded by its number, e.g. `[1]`, and followed by the instructions to cite them.
+///
+/// Returns the context along with the citations, in the order of the numbers.
+pub(crate) fn cite(points: &[ScoredChunk]) -> (String, Vec<Citation>) {
+    let mut context = String::new();
+    let mut citations = Vec::with_capacity(points.len());
+    for (idx, point) in points.iter().enumerate() {
+        let id = idx + 1;
+        context.push_str(&numbered(id, &point.source));
+
+        citations.push(Citation {
+            id,
+            file_id: point.meta.file_id.clone(),
+            filename: point.meta.filename.clone(),
+            chunk_index: point.meta.chunk_index,
+            page: point.meta.page,
+            text: point.source.clone(),
+            score: point.score,
+        });
+    }
+    context.push_str(CITATION_PROMPT);
+    context.push('\n');
+
+    (context, citations)
+}
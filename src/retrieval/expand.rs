@@ -0,0 +1,81 @@
+//! Expand the query into more queries with the chat model, so that the retrieval finds the chunks a single wording misses.
+
+use super::{complete, RagStrategy};
+
+const MULTI_QUERY_SYSTEM_PROMPT: &str = "You write alternative search queries for a question, worded differently from each other and from the question. Answer with one query per line, without numbering.";
+const HYDE_SYSTEM_PROMPT: &str = "You write a short passage answering a question, as it would appear in a document. Answer with the passage only.";
+
+/// Number of paraphrases of the multi-query strategy
+const MULTI_QUERY_COUNT: usize = 3;
+/// Max number of tokens of the paraphrases
+const MAX_PARAPHRASES_TOKENS: u64 = 192;
+/// Max number of tokens of the hypothetical answer
+const MAX_ANSWER_TOKENS: u64 = 256;
+
+/// Generate the queries to retrieve the points of, besides `query` itself: paraphrases of the query for the multi-query strategy, or a hypothetical answer to the query for HyDE.
+///
+/// No query is generated for the single strategy, or if the model fails to answer.
+pub(crate) async fn expand_query(
+    strategy: RagStrategy,
+    model: Option<&str>,
+    query: &str,
+) -> Vec<String> {
+    let (system_prompt, prompt, max_tokens) = match strategy {
+        RagStrategy::Single => return vec![],
+        RagStrategy::MultiQuery => (
+            MULTI_QUERY_SYSTEM_PROMPT,
+            format!(
+                "Question: {}\n\nWrite {} alternative search queries:",
+                query, MULTI_QUERY_COUNT
+            ),
+            MAX_PARAPHRASES_TOKENS,
+        ),
+        RagStrategy::Hyde => (
+            HYDE_SYSTEM_PROMPT,
+            format!("Question: {}\n\nPassage:", query),
+            MAX_ANSWER_TOKENS,
+        ),
+    };
+
+    println!("\n[+] Expanding the user query ({}) ...", strategy);
+
+    let answer = match complete(model, system_prompt, prompt, max_tokens).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            println!(
+                "    * [WARNING] Failed to expand the query, searching the user query only. {}",
+                e
+            );
+            return vec![];
+        }
+    };
+
+    let queries = match strategy {
+        RagStrategy::MultiQuery => answer
+            .lines()
+            .map(|line| {
+                // models tend to number or bullet the lines anyway
+                line.trim()
+                    .trim_start_matches(|c: char| {
+                        c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*')
+                    })
+                    .trim()
+                    .trim_matches('"')
+                    .trim()
+                    .to_string()
+            })
+            .filter(|line| !line.is_empty() && line != query)
+            .take(MULTI_QUERY_COUNT)
+            .collect::<Vec<String>>(),
+        _ => match answer.is_empty() {
+            true => vec![],
+            false => vec![answer],
+        },
+    };
+
+    for query in queries.iter() {
+        println!("    * query: {}", query);
+    }
+
+    queries
+}
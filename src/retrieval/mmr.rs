@@ -0,0 +1,65 @@
+//! Maximal marginal relevance (MMR) selection of the retrieved chunks, which trades relevance for diversity.
+
+use crate::vector_store::ScoredChunk;
+
+/// Select `limit` chunks of the candidates, ranked best first, by maximal marginal relevance.
+///
+/// Each step picks the candidate maximizing `lambda * relevance - (1 - lambda) * similarity`, where the similarity is the highest cosine similarity of its vector to those of the chunks picked so far. The relevance comes from the rank of the candidate, so that it follows the order of the retrieval, or of the reranking. A candidate without vector is similar to no chunk.
+pub(crate) fn mmr(candidates: Vec<ScoredChunk>, limit: usize, lambda: f32) -> Vec<ScoredChunk> {
+    let count = candidates.len();
+    if count <= 1 || limit == 0 {
+        let mut candidates = candidates;
+        candidates.truncate(limit);
+        return candidates;
+    }
+
+    let relevance = (0..count)
+        .map(|rank| 1.0 - rank as f32 / count as f32)
+        .collect::<Vec<f32>>();
+    // highest similarity of each candidate to the picked chunks
+    let mut similarity = vec![0.0f32; count];
+    let mut picked = vec![false; count];
+    let mut order = Vec::with_capacity(limit.min(count));
+
+    while order.len() < limit.min(count) {
+        let mut best: Option<(usize, f32)> = None;
+        for idx in (0..count).filter(|idx| !picked[*idx]) {
+            let score = lambda * relevance[idx] - (1.0 - lambda) * similarity[idx];
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((idx, score));
+            }
+        }
+        let (idx, _) = match best {
+            Some(best) => best,
+            None => break,
+        };
+
+        picked[idx] = true;
+        order.push(idx);
+        for other in (0..count).filter(|other| !picked[*other]) {
+            let sim = cosine(&candidates[idx].vector, &candidates[other].vector);
+            similarity[other] = similarity[other].max(sim);
+        }
+    }
+
+    let mut slots = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|idx| slots[idx].take())
+        .collect()
+}
+
+/// Cosine similarity of two vectors. Zero if either is empty or their sizes differ.
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.is_empty() || a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    match norm_a * norm_b {
+        norm if norm > 0.0 => dot / norm,
+        _ => 0.0,
+    }
+}
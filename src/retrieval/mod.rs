@@ -0,0 +1,164 @@
+//! Stages of the retrieval around the vector store search, e.g. rewriting the query or reranking the retrieved chunks with the chat model, and the assembly of the context.
+
+mod budget;
+mod citations;
+mod expand;
+mod mmr;
+mod rerank;
+mod rewrite;
+
+pub(crate) use budget::fit_context;
+pub(crate) use citations::cite;
+pub(crate) use expand::expand_query;
+pub(crate) use mmr::mmr;
+pub(crate) use rerank::rerank;
+pub(crate) use rewrite::rewrite_query;
+
+use crate::{error::ServerError, vector_store::ScoredChunk, SERVER_INFO};
+use endpoints::chat::{
+    ChatCompletionRequestBuilder, ChatCompletionRequestMessage, ChatCompletionUserMessageContent,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
+
+/// Queries the points are retrieved for, selected by `--rag-strategy` or `rag_strategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RagStrategy {
+    /// The user query only
+    #[default]
+    Single,
+    /// The user query and paraphrases of it
+    MultiQuery,
+    /// The user query and a hypothetical answer to it (HyDE)
+    Hyde,
+}
+impl fmt::Display for RagStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RagStrategy::Single => write!(f, "single"),
+            RagStrategy::MultiQuery => write!(f, "multi-query"),
+            RagStrategy::Hyde => write!(f, "hyde"),
+        }
+    }
+}
+
+/// Merge the points retrieved for several queries, and keep `limit` of them.
+///
+/// The points are ordered by their best rank in the lists, and the ties by the order of the lists. A point retrieved for several queries is kept once, with the score of its first appearance in that order.
+pub(crate) fn merge(lists: Vec<Vec<ScoredChunk>>, limit: usize) -> Vec<ScoredChunk> {
+    let depth = lists.iter().map(Vec::len).max().unwrap_or_default();
+    let mut lists = lists
+        .into_iter()
+        .map(|points| points.into_iter())
+        .collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let mut merged = vec![];
+    for _ in 0..depth {
+        for points in lists.iter_mut() {
+            if let Some(point) = points.next() {
+                // the points without id, if any, cannot be told apart
+                let is_new = match &point.id {
+                    Some(id) => seen.insert(id.clone()),
+                    None => true,
+                };
+                if is_new {
+                    merged.push(point);
+                }
+            }
+        }
+    }
+    merged.truncate(limit);
+
+    merged
+}
+
+/// Ask the chat model for a short completion of `prompt`, following the instructions of `system_prompt`.
+///
+/// The `n_predict` of the model is set back to `--n-predict` afterwards, as the model keeps `max_tokens` as its `n_predict` for the requests answered next.
+pub(crate) async fn complete(
+    model: Option<&str>,
+    system_prompt: &str,
+    prompt: String,
+    max_tokens: u64,
+) -> Result<String, ServerError> {
+    let model = match model {
+        Some(model) => model.to_string(),
+        None => llama_core::utils::chat_model_names()
+            .map_err(|e| ServerError::Operation(e.to_string()))?
+            .first()
+            .cloned()
+            .ok_or(ServerError::Operation(
+                "No chat model is loaded.".to_string(),
+            ))?,
+    };
+
+    let prompt_template = llama_core::utils::chat_prompt_template(Some(&model))
+        .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    // without system prompt, the instructions go along with the prompt
+    let messages = match prompt_template.has_system_prompt() {
+        true => vec![
+            ChatCompletionRequestMessage::new_system_message(system_prompt, None),
+            ChatCompletionRequestMessage::new_user_message(
+                ChatCompletionUserMessageContent::Text(prompt),
+                None,
+            ),
+        ],
+        false => vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(format!("{}\n\n{}", system_prompt, prompt)),
+            None,
+        )],
+    };
+
+    let mut chat_request = ChatCompletionRequestBuilder::new(&model, messages)
+        .with_max_tokens(max_tokens)
+        .build();
+
+    let chat_completion_object = llama_core::chat::chat_completions(&mut chat_request).await;
+    restore_n_predict(&model).await?;
+    let chat_completion_object =
+        chat_completion_object.map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    match chat_completion_object.choices.first() {
+        Some(choice) => Ok(choice.message.content.trim().to_string()),
+        None => Err(ServerError::Operation(
+            "The chat model returned no completion.".to_string(),
+        )),
+    }
+}
+
+/// Set the `n_predict` of the chat model back to `--n-predict`.
+///
+/// llama-core sets the `n_predict` of the model to the `max_tokens` of a request when the request is prepared, and offers no other way to set it. A streamed request is prepared when its stream is created, but runs no inference until the stream is polled, so a request with `--n-predict` as its `max_tokens` is prepared and dropped.
+async fn restore_n_predict(model: &str) -> Result<(), ServerError> {
+    let n_predict = SERVER_INFO
+        .get()
+        .ok_or(ServerError::Operation(
+            "The server info is not set.".to_string(),
+        ))?
+        .rag_config
+        .chat_model
+        .n_predict;
+
+    let mut chat_request = ChatCompletionRequestBuilder::new(
+        model,
+        vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Hello.".to_string()),
+            None,
+        )],
+    )
+    .with_max_tokens(n_predict)
+    .build();
+
+    llama_core::chat::chat_completions_stream(&mut chat_request)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to restore the `n_predict` of the chat model. {}",
+                e
+            ))
+        })
+}
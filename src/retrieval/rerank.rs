@@ -0,0 +1,82 @@
+//! Rerank the retrieved chunks by asking the chat model which of them answer the query best.
+
+use super::complete;
+use crate::vector_store::ScoredChunk;
+
+const SYSTEM_PROMPT: &str = "You rank passages by how well they answer a query. Answer with the numbers of the relevant passages only, most relevant first, separated by commas.";
+
+/// Max number of characters of each passage in the ranking prompt
+const MAX_PASSAGE_CHARS: usize = 1000;
+
+/// Reorder the candidates by their relevance to the query, as judged by the chat model, and keep the best `limit`.
+///
+/// The candidates left out by the model follow in their retrieval order, and the scores are those of the retrieval. If the model fails to answer, the retrieval order is kept.
+pub(crate) async fn rerank(
+    model: Option<&str>,
+    query: &str,
+    mut candidates: Vec<ScoredChunk>,
+    limit: usize,
+) -> Vec<ScoredChunk> {
+    if candidates.len() <= 1 {
+        candidates.truncate(limit);
+        return candidates;
+    }
+
+    println!("\n[+] Reranking the retrieved points ...");
+    println!("    * Number of candidates: {}", candidates.len());
+
+    let mut prompt = format!("Query: {}\n\nPassages:\n", query);
+    for (idx, candidate) in candidates.iter().enumerate() {
+        let text = match candidate.source.char_indices().nth(MAX_PASSAGE_CHARS) {
+            Some((end, _)) => &candidate.source[..end],
+            None => candidate.source.as_str(),
+        };
+        prompt.push_str(&format!("[{}] {}\n", idx + 1, text.replace('\n', " ")));
+    }
+    prompt.push_str("\nNumbers of the relevant passages, most relevant first:");
+
+    // a few tokens per number
+    let max_tokens = candidates.len() as u64 * 4 + 16;
+    let answer = match complete(model, SYSTEM_PROMPT, prompt, max_tokens).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            println!(
+                "    * [WARNING] Failed to rerank, keeping the retrieval order. {}",
+                e
+            );
+            candidates.truncate(limit);
+            return candidates;
+        }
+    };
+
+    println!("    * Ranking: {}", answer);
+
+    let ranking = parse_ranking(&answer, candidates.len());
+    if ranking.is_empty() {
+        println!("    * [WARNING] No passage number in the ranking, keeping the retrieval order.");
+    }
+
+    let mut slots = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    let mut reranked = ranking
+        .into_iter()
+        .filter_map(|idx| slots[idx].take())
+        .collect::<Vec<ScoredChunk>>();
+    reranked.extend(slots.into_iter().flatten());
+    reranked.truncate(limit);
+
+    reranked
+}
+
+/// Get the indexes of the passages numbered in the answer, in their order and without duplicates. Numbers out of `1..=count` are ignored.
+fn parse_ranking(answer: &str, count: usize) -> Vec<usize> {
+    let mut ranking = vec![];
+    for number in answer.split(|c: char| !c.is_ascii_digit()) {
+        if let Ok(number) = number.parse::<usize>() {
+            if (1..=count).contains(&number) && !ranking.contains(&(number - 1)) {
+                ranking.push(number - 1);
+            }
+        }
+    }
+
+    ranking
+}
@@ -0,0 +1,88 @@
+//! Rewrite the last user message of a conversation into a standalone search query with the chat model.
+
+use super::complete;
+use endpoints::chat::{ChatCompletionRequestMessage, ChatCompletionUserMessageContent};
+
+const SYSTEM_PROMPT: &str = "You rewrite the last question of a conversation into a standalone search query. Resolve the references to the earlier turns, e.g. pronouns and ordinals, and keep the names, identifiers and numbers as is. Answer with the query only.";
+
+/// Max number of earlier messages in the rewriting prompt
+const MAX_HISTORY_MESSAGES: usize = 6;
+/// Max number of characters of each earlier message in the rewriting prompt
+const MAX_MESSAGE_CHARS: usize = 1000;
+/// Max number of tokens of the rewritten query
+const MAX_QUERY_TOKENS: u64 = 128;
+
+/// Rewrite `query`, the text of the last user message, into a standalone query, using the earlier user and assistant messages.
+///
+/// The query is kept as is if there is no earlier message, or if the model fails to answer.
+pub(crate) async fn rewrite_query(
+    model: Option<&str>,
+    messages: &[ChatCompletionRequestMessage],
+    query: &str,
+) -> String {
+    let earlier = match messages.split_last() {
+        Some((_, earlier)) => earlier,
+        None => return query.to_string(),
+    };
+    let history = earlier
+        .iter()
+        .filter_map(|message| match message {
+            ChatCompletionRequestMessage::User(message) => match message.content() {
+                ChatCompletionUserMessageContent::Text(text) => Some(("User", text.as_str())),
+                _ => None,
+            },
+            ChatCompletionRequestMessage::Assistant(message) => {
+                message.content().map(|text| ("Assistant", text.as_str()))
+            }
+            _ => None,
+        })
+        .collect::<Vec<(&str, &str)>>();
+    if history.is_empty() {
+        return query.to_string();
+    }
+
+    println!("\n[+] Rewriting the user query ...");
+
+    let mut prompt = "Conversation:\n".to_string();
+    for (role, text) in history[history.len().saturating_sub(MAX_HISTORY_MESSAGES)..].iter() {
+        let text = match text.char_indices().nth(MAX_MESSAGE_CHARS) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        };
+        prompt.push_str(&format!("{}: {}\n", role, text.replace('\n', " ")));
+    }
+    prompt.push_str(&format!("\nLast question: {}\n\nStandalone query:", query));
+
+    match complete(model, SYSTEM_PROMPT, prompt, MAX_QUERY_TOKENS).await {
+        Ok(answer) => {
+            let rewritten = answer
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .trim_start_matches("Standalone query:")
+                .trim()
+                .trim_matches('"')
+                .trim();
+            match rewritten.is_empty() {
+                true => {
+                    println!(
+                        "    * [WARNING] The rewritten query is empty, keeping the user query."
+                    );
+                    query.to_string()
+                }
+                false => {
+                    println!("    * rewritten query: {}", rewritten);
+                    rewritten.to_string()
+                }
+            }
+        }
+        Err(e) => {
+            println!(
+                "    * [WARNING] Failed to rewrite the query, keeping the user query. {}",
+                e
+            );
+            query.to_string()
+        }
+    }
+}
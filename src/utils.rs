@@ -1,3 +1,7 @@
+use std::{
+    fs::{self, Metadata},
+    path::Path,
+};
 use url::Url;
 
 pub(crate) fn print_log_begin_separator(
@@ -35,3 +39,53 @@ pub(crate) fn is_valid_url(url: &str) -> bool {
 pub(crate) fn log(msg: impl std::fmt::Display) {
     println!("{}", msg);
 }
+
+/// List the regular files in `dir` and its subdirectories, with their paths relative to `dir` joined by `/`. Hidden files and directories are skipped.
+pub(crate) fn walk_dir(dir: &Path) -> Vec<(String, Metadata)> {
+    let mut files = vec![];
+
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log(format!(
+                    "[WARNING] Failed to read {}. {}",
+                    current.display(),
+                    e
+                ));
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+
+            if let Ok(relative) = path.strip_prefix(dir) {
+                let filename = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((filename, metadata));
+            }
+        }
+    }
+
+    files
+}
@@ -0,0 +1,775 @@
+//! Vector store embedded in the server, for deployments without a Qdrant server.
+//!
+//! Each collection is kept in memory and persisted in `<collection>.jsonl` of `--vector-store-dir`, a log of the points upserted and removed. The log is appended on every change, and compacted once it holds more removed or replaced points than live ones. Searches compare the query with every point of the collection.
+
+use super::{CollectionInfo, Distance, Filter, Point, PointId, SearchHit, VectorStore};
+use crate::{collections::is_valid_collection_name, error::ServerError};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Extension of the collection logs
+const LOG_EXTENSION: &str = "jsonl";
+/// Number of entries a log may hold besides those of the live points before it is compacted
+const MIN_COMPACTION_ENTRIES: usize = 1024;
+
+/// An entry of the log of a collection.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Entry {
+    /// First entry of the log
+    Create {
+        vector_size: u64,
+        distance: Distance,
+    },
+    Upsert(Point),
+    Remove(Vec<PointId>),
+}
+
+/// A collection of the embedded store.
+#[derive(Debug)]
+struct Collection {
+    vector_size: u64,
+    distance: Distance,
+    points: Vec<Point>,
+    /// Number of entries of the log of the collection
+    log_len: usize,
+}
+impl Collection {
+    fn new(vector_size: u64, distance: Distance) -> Self {
+        Self {
+            vector_size,
+            distance,
+            points: vec![],
+            log_len: 0,
+        }
+    }
+
+    /// Apply the entries in order. A point with the id of an existing point replaces it.
+    fn apply(&mut self, entries: Vec<Entry>) {
+        let mut positions = self.positions();
+        for entry in entries {
+            match entry {
+                Entry::Create { .. } => {}
+                Entry::Upsert(point) => match positions.get(&point.id) {
+                    Some(&idx) => self.points[idx] = point,
+                    None => {
+                        positions.insert(point.id.clone(), self.points.len());
+                        self.points.push(point);
+                    }
+                },
+                Entry::Remove(ids) => {
+                    let ids = ids.into_iter().collect::<HashSet<PointId>>();
+                    self.points.retain(|point| !ids.contains(&point.id));
+                    positions = self.positions();
+                }
+            }
+        }
+    }
+
+    fn positions(&self) -> HashMap<PointId, usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .map(|(idx, point)| (point.id.clone(), idx))
+            .collect()
+    }
+
+    /// Entries rebuilding the collection, one per point.
+    fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        std::iter::once(Entry::Create {
+            vector_size: self.vector_size,
+            distance: self.distance,
+        })
+        .chain(self.points.iter().cloned().map(Entry::Upsert))
+    }
+}
+
+/// Vector store persisted in the files of a directory.
+pub(crate) struct EmbeddedStore {
+    dir: PathBuf,
+    /// Collections loaded from their files, by name
+    collections: Mutex<HashMap<String, Collection>>,
+}
+impl EmbeddedStore {
+    /// Open the store in the given directory. The directory is created if it does not exist.
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Result<Self, ServerError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to create the vector store directory {}. {}",
+                dir.display(),
+                e
+            ))
+        })?;
+
+        Ok(Self {
+            dir,
+            collections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn path(&self, collection_name: &str) -> Result<PathBuf, ServerError> {
+        // the name of a collection is the name of its file
+        if !is_valid_collection_name(collection_name) {
+            return Err(ServerError::Operation(format!(
+                "Invalid collection name: {}",
+                collection_name
+            )));
+        }
+
+        Ok(self
+            .dir
+            .join(format!("{}.{}", collection_name, LOG_EXTENSION)))
+    }
+
+    /// Run `f` on the collection of the given name, loaded from its log on first use. `f` gets `None` if the collection does not exist.
+    fn with_collection<T>(
+        &self,
+        collection_name: &str,
+        f: impl FnOnce(Option<&mut Collection>) -> Result<T, ServerError>,
+    ) -> Result<T, ServerError> {
+        let mut collections = self.collections.lock().map_err(|_| {
+            ServerError::Operation("The embedded vector store is poisoned.".to_string())
+        })?;
+
+        if !collections.contains_key(collection_name) {
+            let path = self.path(collection_name)?;
+            if path.exists() {
+                let collection = load(&path).map_err(|e| {
+                    ServerError::Operation(format!(
+                        "Failed to load the collection {}. {}",
+                        collection_name, e
+                    ))
+                })?;
+                collections.insert(collection_name.to_string(), collection);
+            }
+        }
+
+        f(collections.get_mut(collection_name))
+    }
+
+    /// Append the entries returned by `f` to the log of an existing collection, then apply them to the collection.
+    fn update(
+        &self,
+        collection_name: &str,
+        f: impl FnOnce(&Collection) -> Result<Vec<Entry>, ServerError>,
+    ) -> Result<(), ServerError> {
+        let path = self.path(collection_name)?;
+
+        self.with_collection(collection_name, |collection| {
+            let collection = collection.ok_or_else(|| not_found(collection_name))?;
+            let write_error = |e: String| {
+                ServerError::Operation(format!(
+                    "Failed to write the collection {}. {}",
+                    collection_name, e
+                ))
+            };
+
+            let entries = f(collection)?;
+            if entries.is_empty() {
+                return Ok(());
+            }
+            append(&path, &entries).map_err(write_error)?;
+
+            collection.log_len += entries.len();
+            collection.apply(entries);
+
+            // the log holds an entry per live point once compacted
+            if collection.log_len > 2 * collection.points.len() + MIN_COMPACTION_ENTRIES {
+                compact(collection, &path).map_err(write_error)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+impl VectorStore for EmbeddedStore {
+    async fn collection_info(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionInfo>, ServerError> {
+        self.with_collection(collection_name, |collection| {
+            Ok(collection.map(|collection| CollectionInfo {
+                name: collection_name.to_string(),
+                status: "green".to_string(),
+                points_count: collection.points.len() as u64,
+                vector_size: collection.vector_size,
+                distance: collection.distance.to_string(),
+            }))
+        })
+    }
+
+    async fn collection_names(&self) -> Result<Vec<String>, ServerError> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to read the vector store directory {}. {}",
+                self.dir.display(),
+                e
+            ))
+        })?;
+
+        let mut names = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                match path.extension().is_some_and(|ext| ext == LOG_EXTENSION) {
+                    true => path
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().to_string()),
+                    false => None,
+                }
+            })
+            .filter(|name| is_valid_collection_name(name))
+            .collect::<HashSet<String>>();
+        if let Ok(collections) = self.collections.lock() {
+            names.extend(collections.keys().cloned());
+        }
+
+        Ok(names.into_iter().collect())
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        distance: Distance,
+    ) -> Result<(), ServerError> {
+        self.with_collection(collection_name, |collection| match collection {
+            Some(_) => Err(ServerError::Operation(format!(
+                "The collection {} already exists.",
+                collection_name
+            ))),
+            None => Ok(()),
+        })?;
+
+        let mut collection = Collection::new(vector_size, distance);
+        compact(&mut collection, &self.path(collection_name)?).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to write the collection {}. {}",
+                collection_name, e
+            ))
+        })?;
+        if let Ok(mut collections) = self.collections.lock() {
+            collections.insert(collection_name.to_string(), collection);
+        }
+
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> Result<(), ServerError> {
+        let path = self.path(collection_name)?;
+
+        let mut collections = self.collections.lock().map_err(|_| {
+            ServerError::Operation("The embedded vector store is poisoned.".to_string())
+        })?;
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(ServerError::Operation(format!(
+                    "Failed to remove the collection {}. {}",
+                    collection_name, e
+                )))
+            }
+            _ => {}
+        }
+        collections.remove(collection_name);
+
+        Ok(())
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<Point>) -> Result<(), ServerError> {
+        self.update(collection_name, |collection| {
+            if let Some(point) = points
+                .iter()
+                .find(|point| point.vector.len() as u64 != collection.vector_size)
+            {
+                return Err(ServerError::Operation(format!(
+                    "The vectors of the collection {} have {} dimensions, but a point has {}.",
+                    collection_name,
+                    collection.vector_size,
+                    point.vector.len()
+                )));
+            }
+
+            Ok(points.into_iter().map(Entry::Upsert).collect())
+        })
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        vector: &[f32],
+        limit: usize,
+        score_threshold: f32,
+        filter: Option<&Filter>,
+        with_vector: bool,
+    ) -> Result<Vec<SearchHit>, ServerError> {
+        self.with_collection(collection_name, |collection| {
+            let collection = collection.ok_or_else(|| not_found(collection_name))?;
+            let distance = collection.distance;
+
+            let mut hits = collection
+                .points
+                .iter()
+                .filter(|point| filter.is_none_or(|filter| filter.matches(&point.payload)))
+                .map(|point| (score(distance, vector, &point.vector), point))
+                .filter(|(score, _)| match higher_is_closer(distance) {
+                    true => *score >= score_threshold,
+                    false => *score <= score_threshold,
+                })
+                .collect::<Vec<(f32, &Point)>>();
+
+            hits.sort_by(|(a, _), (b, _)| {
+                let ordering = a.partial_cmp(b).unwrap_or(Ordering::Equal);
+                match higher_is_closer(distance) {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            });
+            hits.truncate(limit);
+
+            Ok(hits
+                .into_iter()
+                .map(|(score, point)| SearchHit {
+                    id: point.id.clone(),
+                    score,
+                    payload: point.payload.clone(),
+                    vector: match with_vector {
+                        true => point.vector.clone(),
+                        false => vec![],
+                    },
+                })
+                .collect())
+        })
+    }
+
+    async fn get(
+        &self,
+        collection_name: &str,
+        ids: &[PointId],
+        with_vector: bool,
+    ) -> Result<Vec<Point>, ServerError> {
+        let ids = ids.iter().collect::<HashSet<&PointId>>();
+
+        self.with_collection(collection_name, |collection| {
+            let collection = collection.ok_or_else(|| not_found(collection_name))?;
+
+            Ok(collection
+                .points
+                .iter()
+                .filter(|point| ids.contains(&point.id))
+                .map(|point| Point {
+                    id: point.id.clone(),
+                    vector: match with_vector {
+                        true => point.vector.clone(),
+                        false => vec![],
+                    },
+                    payload: point.payload.clone(),
+                })
+                .collect())
+        })
+    }
+
+    async fn scroll(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+    ) -> Result<Vec<Point>, ServerError> {
+        self.with_collection(collection_name, |collection| {
+            let collection = collection.ok_or_else(|| not_found(collection_name))?;
+
+            Ok(collection
+                .points
+                .iter()
+                .filter(|point| filter.matches(&point.payload))
+                .cloned()
+                .collect())
+        })
+    }
+
+    async fn delete(&self, collection_name: &str, filter: &Filter) -> Result<(), ServerError> {
+        self.update(collection_name, |collection| {
+            let ids = collection
+                .points
+                .iter()
+                .filter(|point| filter.matches(&point.payload))
+                .map(|point| point.id.clone())
+                .collect::<Vec<PointId>>();
+
+            Ok(match ids.is_empty() {
+                true => vec![],
+                false => vec![Entry::Remove(ids)],
+            })
+        })
+    }
+
+    async fn delete_ids(
+        &self,
+        collection_name: &str,
+        ids: Vec<PointId>,
+    ) -> Result<(), ServerError> {
+        self.update(collection_name, |collection| {
+            let ids = ids.into_iter().collect::<HashSet<PointId>>();
+            let ids = collection
+                .points
+                .iter()
+                .filter(|point| ids.contains(&point.id))
+                .map(|point| point.id.clone())
+                .collect::<Vec<PointId>>();
+
+            Ok(match ids.is_empty() {
+                true => vec![],
+                false => vec![Entry::Remove(ids)],
+            })
+        })
+    }
+}
+
+/// Load a collection by replaying its log.
+fn load(path: &Path) -> Result<Collection, String> {
+    let log = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    let mut collection: Option<Collection> = None;
+    let mut log_len = 0;
+    let mut skipped = false;
+    for (idx, line) in log.lines().enumerate() {
+        log_len += 1;
+        let entry = match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => entry,
+            // e.g. the last entry, if the server stopped while appending it
+            Err(e) => {
+                skipped = true;
+                println!(
+                    "    * [WARNING] Skipped entry {} of {}. {}",
+                    idx + 1,
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        match (&mut collection, entry) {
+            (
+                None,
+                Entry::Create {
+                    vector_size,
+                    distance,
+                },
+            ) => collection = Some(Collection::new(vector_size, distance)),
+            (None, _) => return Err("The log does not start with the collection.".to_string()),
+            (Some(collection), entry) => collection.apply(vec![entry]),
+        }
+    }
+
+    let mut collection =
+        collection.ok_or_else(|| "The log does not start with the collection.".to_string())?;
+    collection.log_len = log_len;
+
+    // the next entries would be appended to a truncated line
+    if skipped {
+        compact(&mut collection, path)?;
+    }
+
+    Ok(collection)
+}
+
+/// Append the entries to the log at `path`, one per line.
+fn append(path: &Path, entries: &[Entry]) -> Result<(), String> {
+    let mut log = vec![];
+    for entry in entries {
+        serde_json::to_writer(&mut log, entry).map_err(|e| e.to_string())?;
+        log.push(b'\n');
+    }
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&log))
+        .map_err(|e| e.to_string())
+}
+
+/// Rewrite the log of the collection with an entry per point.
+fn compact(collection: &mut Collection, path: &Path) -> Result<(), String> {
+    let mut log = vec![];
+    for entry in collection.entries() {
+        serde_json::to_writer(&mut log, &entry).map_err(|e| e.to_string())?;
+        log.push(b'\n');
+    }
+
+    // replace the log at once, so that it is never left half written
+    let tmp = path.with_extension(format!("{}.tmp", LOG_EXTENSION));
+    fs::write(&tmp, log)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| e.to_string())?;
+    collection.log_len = collection.points.len() + 1;
+
+    Ok(())
+}
+
+fn not_found(collection_name: &str) -> ServerError {
+    ServerError::Operation(format!("Not found collection: {}", collection_name))
+}
+
+/// Whether a higher score means a closer point. Euclid and Manhattan scores are distances, as in Qdrant.
+fn higher_is_closer(distance: Distance) -> bool {
+    matches!(distance, Distance::Cosine | Distance::Dot)
+}
+
+fn score(distance: Distance, a: &[f32], b: &[f32]) -> f32 {
+    let pairs = a.iter().zip(b.iter());
+    match distance {
+        Distance::Cosine => {
+            let dot: f32 = pairs.map(|(x, y)| x * y).sum();
+            let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+            match norm_a * norm_b {
+                norm if norm > 0.0 => dot / norm,
+                _ => 0.0,
+            }
+        }
+        Distance::Dot => pairs.map(|(x, y)| x * y).sum(),
+        Distance::Euclid => pairs.map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt(),
+        Distance::Manhattan => pairs.map(|(x, y)| (x - y).abs()).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map, Value};
+
+    fn store(name: &str) -> EmbeddedStore {
+        let dir =
+            std::env::temp_dir().join(format!("embedded_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        EmbeddedStore::new(dir).unwrap()
+    }
+
+    fn point(id: u64, vector: Vec<f32>, payload: Value) -> Point {
+        Point {
+            id: PointId::Num(id),
+            vector,
+            payload: payload_of(payload),
+        }
+    }
+
+    fn payload_of(payload: Value) -> Map<String, Value> {
+        match payload {
+            Value::Object(payload) => payload,
+            _ => Map::new(),
+        }
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<PointId> {
+        hits.iter().map(|hit| hit.id.clone()).collect()
+    }
+
+    #[test]
+    fn score_per_distance() {
+        let a = [1.0, 2.0];
+        let b = [3.0, -1.0];
+
+        assert!((score(Distance::Cosine, &a, &b) - 1.0 / 50f32.sqrt()).abs() < 1e-6);
+        assert_eq!(score(Distance::Dot, &a, &b), 1.0);
+        assert_eq!(score(Distance::Euclid, &a, &b), 13f32.sqrt());
+        assert_eq!(score(Distance::Manhattan, &a, &b), 5.0);
+        // a zero vector has no direction
+        assert_eq!(score(Distance::Cosine, &a, &[0.0, 0.0]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn closer_points_first() {
+        for distance in [
+            Distance::Cosine,
+            Distance::Euclid,
+            Distance::Dot,
+            Distance::Manhattan,
+        ] {
+            let store = store(&format!("closer_{}", distance));
+            store.create_collection("docs", 2, distance).await.unwrap();
+            store
+                .upsert(
+                    "docs",
+                    vec![
+                        point(1, vec![-1.0, 0.1], json!({})),
+                        point(2, vec![1.0, 0.0], json!({})),
+                        point(3, vec![0.6, 0.6], json!({})),
+                    ],
+                )
+                .await
+                .unwrap();
+
+            let threshold = match higher_is_closer(distance) {
+                true => f32::MIN,
+                false => f32::MAX,
+            };
+            let hits = store
+                .search("docs", &[1.0, 0.0], 3, threshold, None, false)
+                .await
+                .unwrap();
+            assert_eq!(
+                ids(&hits),
+                vec![PointId::Num(2), PointId::Num(3), PointId::Num(1)],
+                "{}",
+                distance
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn score_threshold_of_distances() {
+        for (distance, threshold) in [(Distance::Euclid, 1.0), (Distance::Manhattan, 1.0)] {
+            let store = store(&format!("threshold_{}", distance));
+            store.create_collection("docs", 2, distance).await.unwrap();
+            store
+                .upsert(
+                    "docs",
+                    vec![
+                        point(1, vec![0.5, 0.0], json!({})),
+                        point(2, vec![3.0, 0.0], json!({})),
+                    ],
+                )
+                .await
+                .unwrap();
+
+            // the threshold of distances is an upper bound
+            let hits = store
+                .search("docs", &[0.0, 0.0], 10, threshold, None, false)
+                .await
+                .unwrap();
+            assert_eq!(ids(&hits), vec![PointId::Num(1)], "{}", distance);
+        }
+    }
+
+    #[test]
+    fn filter_nested_and_array_keys() {
+        let payload = payload_of(json!({
+            "source": "a.pdf",
+            "meta": { "lang": "en", "tags": ["faq", "billing"] },
+        }));
+
+        assert!(Filter::default().matches(&payload));
+        assert!(Filter::default()
+            .must("meta.lang", vec!["fr".to_string(), "en".to_string()])
+            .matches(&payload));
+        assert!(Filter::default()
+            .must("meta.tags", vec!["billing".to_string()])
+            .matches(&payload));
+        assert!(!Filter::default()
+            .must("meta.tags", vec!["sales".to_string()])
+            .matches(&payload));
+        // all the conditions must match
+        assert!(!Filter::default()
+            .must("source", vec!["a.pdf".to_string()])
+            .must("meta.lang", vec!["fr".to_string()])
+            .matches(&payload));
+        assert!(!Filter::default()
+            .must("meta.missing", vec!["en".to_string()])
+            .matches(&payload));
+    }
+
+    #[tokio::test]
+    async fn reload_collections() {
+        let store = store("reload");
+        store
+            .create_collection("docs", 2, Distance::Euclid)
+            .await
+            .unwrap();
+        store
+            .upsert(
+                "docs",
+                vec![
+                    point(1, vec![1.0, 0.0], json!({ "source": "a.pdf" })),
+                    point(2, vec![0.0, 1.0], json!({ "source": "b.pdf" })),
+                    point(3, vec![1.0, 1.0], json!({ "source": "b.pdf" })),
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .upsert(
+                "docs",
+                vec![point(1, vec![2.0, 0.0], json!({ "source": "c.pdf" }))],
+            )
+            .await
+            .unwrap();
+        store
+            .delete_ids("docs", vec![PointId::Num(2)])
+            .await
+            .unwrap();
+
+        let reloaded = EmbeddedStore::new(store.dir.clone()).unwrap();
+        assert_eq!(reloaded.collection_names().await.unwrap(), vec!["docs"]);
+        let info = reloaded.collection_info("docs").await.unwrap().unwrap();
+        assert_eq!(
+            (info.points_count, info.vector_size, info.distance.as_str()),
+            (2, 2, "Euclid")
+        );
+        let points = reloaded
+            .get("docs", &[PointId::Num(1), PointId::Num(3)], true)
+            .await
+            .unwrap();
+        assert_eq!(points[0].vector, vec![2.0, 0.0]);
+        assert_eq!(points[0].payload["source"], "c.pdf");
+        assert_eq!(points[1].payload["source"], "b.pdf");
+    }
+
+    #[tokio::test]
+    async fn skip_truncated_entries() {
+        let store = store("truncated");
+        store
+            .create_collection("docs", 2, Distance::Dot)
+            .await
+            .unwrap();
+        store
+            .upsert("docs", vec![point(1, vec![1.0, 0.0], json!({}))])
+            .await
+            .unwrap();
+        let path = store.path("docs").unwrap();
+        let mut log = fs::read_to_string(&path).unwrap();
+        log.push_str(r#"{"upsert":{"id":2,"vec"#);
+        fs::write(&path, log).unwrap();
+
+        let reloaded = EmbeddedStore::new(store.dir.clone()).unwrap();
+        reloaded
+            .upsert("docs", vec![point(3, vec![0.0, 1.0], json!({}))])
+            .await
+            .unwrap();
+
+        let reloaded = EmbeddedStore::new(store.dir.clone()).unwrap();
+        let points = reloaded.scroll("docs", &Filter::default()).await.unwrap();
+        let ids = points.into_iter().map(|point| point.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![PointId::Num(1), PointId::Num(3)]);
+    }
+
+    #[test]
+    fn compact_log() {
+        let dir =
+            std::env::temp_dir().join(format!("embedded_store_compact_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("docs.jsonl");
+
+        let mut collection = Collection::new(2, Distance::Cosine);
+        collection.apply(vec![
+            Entry::Upsert(point(1, vec![1.0, 0.0], json!({}))),
+            Entry::Upsert(point(2, vec![0.0, 1.0], json!({}))),
+            Entry::Upsert(point(1, vec![0.5, 0.5], json!({}))),
+            Entry::Remove(vec![PointId::Num(2)]),
+        ]);
+        collection.log_len = 5;
+        compact(&mut collection, &path).unwrap();
+
+        assert_eq!(collection.log_len, 2);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.points.len(), 1);
+        assert_eq!(loaded.points[0].vector, vec![0.5, 0.5]);
+    }
+}
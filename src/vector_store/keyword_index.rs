@@ -0,0 +1,538 @@
+//! BM25 keyword index of the points of each collection, kept along with the vectors.
+//!
+//! The index of a collection is kept in memory and persisted in `<collection>.jsonl` of `--keyword-index-dir`, a log of the points added and removed. The log is appended on every change, and compacted once it holds more removed points than live ones. The index keeps the terms and the metadata of the points for filtering, but not their text, which is read from the vector store.
+
+use super::{Filter, Point, PointId, SearchHit};
+use crate::{collections::is_valid_collection_name, error::ServerError, KEYWORD_INDEX_DIR};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Fields of the payloads left out of the index, as they are never filtered on
+const UNINDEXED_FIELDS: [&str; 2] = ["source", "chunk_hash"];
+/// Number of entries a log may hold besides those of the live points before it is compacted
+const MIN_COMPACTION_ENTRIES: usize = 1024;
+
+/// Term frequency saturation of BM25
+const K1: f32 = 1.2;
+/// Document length normalization of BM25
+const B: f32 = 0.75;
+
+/// Indexes loaded from their files, by collection name.
+static INDEXES: Lazy<Mutex<HashMap<String, Index>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// An indexed point, as persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedPoint {
+    id: PointId,
+    /// Payload of the point, without the unindexed fields
+    payload: Map<String, Value>,
+    /// Frequency of each term in the text of the point
+    terms: BTreeMap<String, u32>,
+    /// Number of terms in the text of the point
+    len: u32,
+}
+
+/// An entry of the log of an index.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Entry {
+    Add(IndexedPoint),
+    Remove(Vec<PointId>),
+}
+
+/// Index of the points of a collection.
+#[derive(Debug, Default)]
+struct Index {
+    points: HashMap<PointId, IndexedPoint>,
+    /// Points containing each term
+    postings: HashMap<String, HashSet<PointId>>,
+    /// Sum of the lengths of the points
+    total_len: u64,
+    /// Number of entries of the log
+    log_len: usize,
+}
+impl Index {
+    fn insert(&mut self, point: IndexedPoint) {
+        self.remove(&point.id);
+
+        for term in point.terms.keys() {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(point.id.clone());
+        }
+        self.total_len += point.len as u64;
+        self.points.insert(point.id.clone(), point);
+    }
+
+    fn remove(&mut self, id: &PointId) {
+        if let Some(point) = self.points.remove(id) {
+            for term in point.terms.keys() {
+                if let Some(ids) = self.postings.get_mut(term) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+            self.total_len -= point.len as u64;
+        }
+    }
+
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Add(point) => self.insert(point),
+            Entry::Remove(ids) => {
+                for id in ids.iter() {
+                    self.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Score the points containing any of the query terms, best first. The hits carry the indexed payloads, without the text of the points.
+    fn search(&self, query: &str, limit: usize, filter: Option<&Filter>) -> Vec<SearchHit> {
+        let n = self.points.len() as f32;
+        let avg_len = match self.points.is_empty() {
+            true => return vec![],
+            false => self.total_len as f32 / n,
+        };
+
+        let mut scores: HashMap<&PointId, f32> = HashMap::new();
+        let terms = tokenize(query).into_iter().collect::<HashSet<String>>();
+        for term in terms.iter() {
+            let ids = match self.postings.get(term) {
+                Some(ids) => ids,
+                None => continue,
+            };
+
+            let df = ids.len() as f32;
+            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+            for id in ids {
+                let point = &self.points[id];
+                let tf = point.terms.get(term).copied().unwrap_or_default() as f32;
+                let norm = K1 * (1.0 - B + B * point.len as f32 / avg_len);
+                *scores.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .map(|(id, score)| (&self.points[id], score))
+            .filter(|(point, _)| filter.is_none_or(|filter| filter.matches(&point.payload)))
+            .collect::<Vec<(&IndexedPoint, f32)>>();
+        hits.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        hits.truncate(limit);
+
+        hits.into_iter()
+            .map(|(point, score)| SearchHit {
+                id: point.id.clone(),
+                score,
+                payload: point.payload.clone(),
+                vector: vec![],
+            })
+            .collect()
+    }
+}
+
+/// Split a text into lowercase terms. Identifiers such as `ERR_42`, `SKU-1234` or `std::fs` are terms, and so are their parts.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    for word in text.split(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))) {
+        let word = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+
+        if word.contains(|c: char| !c.is_alphanumeric()) {
+            terms.extend(
+                word.split(|c: char| !c.is_alphanumeric())
+                    .filter(|part| !part.is_empty())
+                    .map(|part| part.to_string()),
+            );
+        }
+        terms.push(word);
+    }
+
+    terms
+}
+
+fn index_point(point: &Point) -> IndexedPoint {
+    let text = point
+        .payload
+        .get("source")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    let mut terms = BTreeMap::new();
+    let mut len = 0;
+    for term in tokenize(text) {
+        *terms.entry(term).or_default() += 1;
+        len += 1;
+    }
+
+    IndexedPoint {
+        id: point.id.clone(),
+        payload: point
+            .payload
+            .iter()
+            .filter(|(key, _)| !UNINDEXED_FIELDS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        terms,
+        len,
+    }
+}
+
+fn path(collection_name: &str) -> Result<PathBuf, ServerError> {
+    if !is_valid_collection_name(collection_name) {
+        return Err(ServerError::Operation(format!(
+            "Invalid collection name: {}",
+            collection_name
+        )));
+    }
+
+    Ok(dir()?.join(format!("{}.jsonl", collection_name)))
+}
+
+fn dir() -> Result<&'static PathBuf, ServerError> {
+    KEYWORD_INDEX_DIR.get().ok_or(ServerError::Operation(
+        "The keyword index directory is not set.".to_string(),
+    ))
+}
+
+/// Whether the index of the collection exists.
+pub(super) fn exists(collection_name: &str) -> Result<bool, ServerError> {
+    let indexes = lock()?;
+
+    Ok(indexes.contains_key(collection_name) || path(collection_name)?.exists())
+}
+
+/// Index the points. A point with the id of an indexed point replaces it. The index is created if it does not exist.
+pub(super) fn add(collection_name: &str, points: &[Point]) -> Result<(), ServerError> {
+    update(collection_name, true, |_| {
+        points
+            .iter()
+            .map(|point| Entry::Add(index_point(point)))
+            .collect()
+    })
+}
+
+/// Remove the points matching the filter.
+pub(super) fn remove(collection_name: &str, filter: &Filter) -> Result<(), ServerError> {
+    update(collection_name, false, |index| {
+        let ids = index
+            .points
+            .values()
+            .filter(|point| filter.matches(&point.payload))
+            .map(|point| point.id.clone())
+            .collect::<Vec<PointId>>();
+        match ids.is_empty() {
+            true => vec![],
+            false => vec![Entry::Remove(ids)],
+        }
+    })
+}
+
+/// Remove the points of the given ids.
+pub(super) fn remove_ids(collection_name: &str, ids: &[PointId]) -> Result<(), ServerError> {
+    update(collection_name, false, |index| {
+        let ids = ids
+            .iter()
+            .filter(|id| index.points.contains_key(id))
+            .cloned()
+            .collect::<Vec<PointId>>();
+        match ids.is_empty() {
+            true => vec![],
+            false => vec![Entry::Remove(ids)],
+        }
+    })
+}
+
+/// Drop the index of the collection.
+pub(super) fn drop(collection_name: &str) -> Result<(), ServerError> {
+    let mut indexes = lock()?;
+
+    match fs::remove_file(path(collection_name)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(ServerError::Operation(format!(
+                "Failed to remove the keyword index of {}. {}",
+                collection_name, e
+            )))
+        }
+        _ => {}
+    }
+    indexes.remove(collection_name);
+
+    Ok(())
+}
+
+/// Search the points matching the query best, best first. The hits carry the indexed payloads, without the text of the points.
+pub(super) fn search(
+    collection_name: &str,
+    query: &str,
+    limit: usize,
+    filter: Option<&Filter>,
+) -> Result<Vec<SearchHit>, ServerError> {
+    let mut indexes = lock()?;
+
+    Ok(load(&mut indexes, collection_name)?
+        .map(|index| index.search(query, limit, filter))
+        .unwrap_or_default())
+}
+
+fn lock() -> Result<std::sync::MutexGuard<'static, HashMap<String, Index>>, ServerError> {
+    INDEXES
+        .lock()
+        .map_err(|_| ServerError::Operation("The keyword index is poisoned.".to_string()))
+}
+
+/// Get the index of the collection, loaded from its log on first use. Returns `None` if the index does not exist.
+fn load<'a>(
+    indexes: &'a mut HashMap<String, Index>,
+    collection_name: &str,
+) -> Result<Option<&'a mut Index>, ServerError> {
+    if !indexes.contains_key(collection_name) {
+        let path = path(collection_name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let log = fs::read_to_string(&path).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to load the keyword index of {}. {}",
+                collection_name, e
+            ))
+        })?;
+
+        let mut index = Index::default();
+        let mut skipped = false;
+        for (idx, line) in log.lines().enumerate() {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => index.apply(entry),
+                // e.g. the last entry, if the server stopped while appending it
+                Err(e) => {
+                    skipped = true;
+                    println!(
+                        "    * [WARNING] Skipped entry {} of the keyword index of {}. {}",
+                        idx + 1,
+                        collection_name,
+                        e
+                    )
+                }
+            }
+            index.log_len += 1;
+        }
+
+        // the next entries would be appended to a truncated line
+        if skipped {
+            compact(&mut index, &path).map_err(|e| {
+                ServerError::Operation(format!(
+                    "Failed to write the keyword index of {}. {}",
+                    collection_name, e
+                ))
+            })?;
+        }
+        indexes.insert(collection_name.to_string(), index);
+    }
+
+    Ok(indexes.get_mut(collection_name))
+}
+
+/// Apply the entries returned by `f` to the index of the collection, and append them to its log. Without the index, nothing is done unless `create` is set.
+fn update(
+    collection_name: &str,
+    create: bool,
+    f: impl FnOnce(&Index) -> Vec<Entry>,
+) -> Result<(), ServerError> {
+    let mut indexes = lock()?;
+    if load(&mut indexes, collection_name)?.is_none() {
+        if !create {
+            return Ok(());
+        }
+        indexes.insert(collection_name.to_string(), Index::default());
+    }
+    let index = indexes
+        .get_mut(collection_name)
+        .ok_or_else(|| ServerError::Operation("The keyword index is not loaded.".to_string()))?;
+
+    let entries = f(index);
+    let path = path(collection_name)?;
+    let write_error = |e: String| {
+        ServerError::Operation(format!(
+            "Failed to write the keyword index of {}. {}",
+            collection_name, e
+        ))
+    };
+
+    let mut log = vec![];
+    for entry in entries.iter() {
+        serde_json::to_writer(&mut log, entry).map_err(|e| write_error(e.to_string()))?;
+        log.push(b'\n');
+    }
+    fs::create_dir_all(dir()?)
+        .and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?
+                .write_all(&log)
+        })
+        .map_err(|e| write_error(e.to_string()))?;
+
+    index.log_len += entries.len();
+    for entry in entries {
+        index.apply(entry);
+    }
+
+    // the log holds an entry per live point once compacted
+    if index.log_len > 2 * index.points.len() + MIN_COMPACTION_ENTRIES {
+        compact(index, &path).map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+/// Rewrite the log of the index with an entry per point.
+fn compact(index: &mut Index, path: &Path) -> Result<(), String> {
+    let mut log = vec![];
+    for point in index.points.values() {
+        serde_json::to_writer(&mut log, &Entry::Add(point.clone())).map_err(|e| e.to_string())?;
+        log.push(b'\n');
+    }
+
+    // replace the log at once, so that it is never left half written
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(&tmp, log)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| e.to_string())?;
+    index.log_len = index.points.len();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Use a directory of the process for the indexes of the tests.
+    fn init() {
+        KEYWORD_INDEX_DIR.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("keyword_index_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            dir
+        });
+    }
+
+    fn point(id: u64, text: &str) -> Point {
+        let mut payload = Map::new();
+        payload.insert("source".to_string(), json!(text));
+
+        Point {
+            id: PointId::Num(id),
+            vector: vec![],
+            payload,
+        }
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<PointId> {
+        hits.iter().map(|hit| hit.id.clone()).collect()
+    }
+
+    /// Forget the loaded index, so that it is loaded from its log again.
+    fn unload(collection_name: &str) {
+        lock().unwrap().remove(collection_name);
+    }
+
+    #[test]
+    fn tokenize_identifiers() {
+        let terms = tokenize("Error ERR_42 for SKU-1234, see std::fs.");
+
+        for term in [
+            "error", "err_42", "err", "42", "for", "sku-1234", "sku", "1234", "see", "std::fs",
+            "std", "fs",
+        ] {
+            assert!(terms.contains(&term.to_string()), "{}", term);
+        }
+        assert!(!terms
+            .iter()
+            .any(|term| term.ends_with('.') || term.ends_with(',')));
+    }
+
+    #[test]
+    fn rank_by_bm25() {
+        let mut index = Index::default();
+        for point in [
+            point(1, "the disk is full"),
+            point(2, "the network is down"),
+            point(3, "the disk quota of the disk is exceeded"),
+        ] {
+            index.insert(index_point(&point));
+        }
+
+        let hits = index.search("disk quota", 10, None);
+        assert_eq!(ids(&hits), vec![PointId::Num(3), PointId::Num(1)]);
+        // a rare term weighs more than a term of every point
+        let hits = index.search("the network", 10, None);
+        assert_eq!(hits[0].id, PointId::Num(2));
+        assert!(hits[0].score > 2.0 * hits[1].score);
+        // the exact identifier matches
+        index.insert(index_point(&point(4, "Failed with ERR_42")));
+        let hits = index.search("err_42", 10, None);
+        assert_eq!(ids(&hits), vec![PointId::Num(4)]);
+    }
+
+    #[test]
+    fn skip_truncated_entries() {
+        init();
+        add("truncated", &[point(1, "disk full")]).unwrap();
+        let path = path("truncated").unwrap();
+        let mut log = fs::read_to_string(&path).unwrap();
+        log.push_str(r#"{"add":{"id":2,"payl"#);
+        fs::write(&path, log).unwrap();
+
+        unload("truncated");
+        assert_eq!(
+            ids(&search("truncated", "disk", 10, None).unwrap()),
+            vec![PointId::Num(1)]
+        );
+        // the entries after the truncated one are kept
+        add("truncated", &[point(3, "disk quota")]).unwrap();
+        unload("truncated");
+        let mut hits = ids(&search("truncated", "disk", 10, None).unwrap());
+        hits.sort_by_key(|id| format!("{:?}", id));
+        assert_eq!(hits, vec![PointId::Num(1), PointId::Num(3)]);
+    }
+
+    #[test]
+    fn compact_log() {
+        init();
+        let points = (0..MIN_COMPACTION_ENTRIES as u64 + 10)
+            .map(|id| point(id, "disk full"))
+            .collect::<Vec<Point>>();
+        add("compact", &points).unwrap();
+        for point in points.iter().skip(2) {
+            remove_ids("compact", std::slice::from_ref(&point.id)).unwrap();
+        }
+
+        // the log holds the live points only once compacted
+        let log = fs::read_to_string(path("compact").unwrap()).unwrap();
+        assert!(log.lines().count() < MIN_COMPACTION_ENTRIES);
+        unload("compact");
+        let mut hits = ids(&search("compact", "disk", 10, None).unwrap());
+        hits.sort_by_key(|id| format!("{:?}", id));
+        assert_eq!(hits, vec![PointId::Num(0), PointId::Num(1)]);
+    }
+}
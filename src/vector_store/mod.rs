@@ -0,0 +1,1015 @@
+//! Persist the embeddings of document chunks in the vector store and retrieve them.
+//!
+//! The vector store is either a Qdrant server or the store embedded in the server, selected by `--vector-store`. The text of the chunks is also indexed in a BM25 keyword index, for the keyword and hybrid retrieval modes.
+
+mod embedded_store;
+mod keyword_index;
+mod qdrant_store;
+
+pub(crate) use embedded_store::EmbeddedStore;
+pub(crate) use qdrant_store::QdrantStore;
+
+use crate::{
+    error::ServerError,
+    ingest::{ChunkMeta, DocChunk},
+    registry, VECTOR_STORE,
+};
+use endpoints::embeddings::EmbeddingObject;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+/// Rank offset of reciprocal rank fusion, which damps the weight of the top ranks.
+const RRF_K: f32 = 60.0;
+/// Number of candidates taken from each list before the fusion, as a multiple of the limit.
+const HYBRID_CANDIDATES: usize = 4;
+/// Max number of candidates taken from each list before the fusion, unless the limit is higher
+const MAX_HYBRID_CANDIDATES: usize = 100;
+
+/// A retrieved chunk and the metadata of the document it comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ScoredChunk {
+    /// Source of the context
+    pub(crate) source: String,
+    /// Points vector distance to the query vector
+    pub(crate) score: f32,
+    #[serde(flatten)]
+    pub(crate) meta: ChunkMeta,
+    /// Id of the point
+    #[serde(skip)]
+    pub(crate) id: Option<PointId>,
+    /// Vector of the point, if retrieved along with the point
+    #[serde(skip)]
+    pub(crate) vector: Vec<f32>,
+}
+
+/// Same as `endpoints::rag::RetrieveObject`, except that the retrieved points carry the metadata of their chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RetrieveObject {
+    /// The retrieved sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) points: Option<Vec<ScoredChunk>>,
+    /// The number of similar points to retrieve
+    pub(crate) limit: usize,
+    /// The score threshold
+    pub(crate) score_threshold: f32,
+    /// The query the points are retrieved for, if the last user message is rewritten
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rewritten_query: Option<String>,
+    /// The queries generated by the retrieval strategy, if any, which the points are retrieved for as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) expanded_queries: Option<Vec<String>>,
+}
+
+/// Restricts the retrieval to the chunks of some documents. All the fields set must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RetrieveFilter {
+    /// Id of the archived file, or ids of which any matches
+    #[serde(default)]
+    pub(crate) file_id: Option<OneOrMany>,
+    /// Name of the archived file, or names of which any matches
+    #[serde(default)]
+    pub(crate) filename: Option<OneOrMany>,
+    /// Tags of the documents, each with a value or values of which any matches
+    #[serde(default)]
+    pub(crate) tags: BTreeMap<String, OneOrMany>,
+}
+impl RetrieveFilter {
+    /// Translate the filter to a payload filter. Returns `None` if no field is set.
+    pub(crate) fn to_filter(&self) -> Option<Filter> {
+        let mut filter = Filter::default();
+        if let Some(file_id) = &self.file_id {
+            filter = filter.must("file_id", file_id.values());
+        }
+        if let Some(filename) = &self.filename {
+            filter = filter.must("filename", filename.values());
+        }
+        for (key, value) in self.tags.iter() {
+            filter = filter.must(format!("tags.{}", key), value.values());
+        }
+
+        match filter.is_empty() {
+            true => None,
+            false => Some(filter),
+        }
+    }
+}
+
+/// A value of a filter field, or a list of values of which any matches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+impl OneOrMany {
+    fn values(&self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value.clone()],
+            OneOrMany::Many(values) => values.clone(),
+        }
+    }
+}
+
+/// Payload filter of the points. All of its conditions must match.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    conditions: Vec<Condition>,
+}
+impl Filter {
+    /// Whether the filter has no condition, i.e. matches all the points.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    /// Add the condition that the payload value at `key` is any of `values`. Nested keys are separated by dots.
+    fn must(mut self, key: impl Into<String>, values: Vec<String>) -> Self {
+        self.conditions.push(Condition {
+            key: key.into(),
+            values,
+        });
+        self
+    }
+
+    /// The filter in the JSON format of Qdrant filters.
+    pub(crate) fn to_json(&self) -> Value {
+        let must = self
+            .conditions
+            .iter()
+            .map(|condition| match condition.values.as_slice() {
+                [value] => json!({ "key": condition.key, "match": { "value": value } }),
+                values => json!({ "key": condition.key, "match": { "any": values } }),
+            })
+            .collect::<Vec<Value>>();
+
+        json!({ "must": must })
+    }
+
+    /// Whether the payload matches all the conditions. An array value matches if any of its items does, as in Qdrant.
+    pub(crate) fn matches(&self, payload: &Map<String, Value>) -> bool {
+        self.conditions.iter().all(|condition| {
+            let mut keys = condition.key.split('.');
+            let first = keys.next().and_then(|key| payload.get(key));
+            let value = keys.fold(first, |value, key| value.and_then(|value| value.get(key)));
+
+            let is_match = |value: &Value| {
+                value
+                    .as_str()
+                    .is_some_and(|value| condition.values.iter().any(|v| v == value))
+            };
+            match value {
+                Some(Value::Array(items)) => items.iter().any(is_match),
+                Some(value) => is_match(value),
+                None => false,
+            }
+        })
+    }
+}
+
+/// Matches the points whose payload value at `key` is any of `values`.
+#[derive(Debug, Clone)]
+struct Condition {
+    key: String,
+    values: Vec<String>,
+}
+
+/// Id of a point, in the format of Qdrant point ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PointId {
+    Uuid(String),
+    Num(u64),
+}
+
+/// A point of the vector store: the vector of a chunk along with its payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Point {
+    pub(crate) id: PointId,
+    #[serde(default)]
+    pub(crate) vector: Vec<f32>,
+    #[serde(default)]
+    pub(crate) payload: Map<String, Value>,
+}
+
+/// A point found by a search.
+#[derive(Debug, Clone)]
+pub(crate) struct SearchHit {
+    pub(crate) id: PointId,
+    pub(crate) score: f32,
+    pub(crate) payload: Map<String, Value>,
+    /// Vector of the point. Empty unless the search asks for the vectors.
+    pub(crate) vector: Vec<f32>,
+}
+
+/// Distance function of the vectors of a collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Distance {
+    #[default]
+    Cosine,
+    Euclid,
+    Dot,
+    Manhattan,
+}
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distance::Cosine => write!(f, "Cosine"),
+            Distance::Euclid => write!(f, "Euclid"),
+            Distance::Dot => write!(f, "Dot"),
+            Distance::Manhattan => write!(f, "Manhattan"),
+        }
+    }
+}
+
+/// Summary of a collection.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CollectionInfo {
+    pub(crate) name: String,
+    /// Status of the collection reported by the store, e.g. `green`
+    pub(crate) status: String,
+    /// Number of points in the collection
+    pub(crate) points_count: u64,
+    /// Number of dimensions of the vectors
+    pub(crate) vector_size: u64,
+    /// Distance function of the vectors, e.g. `Cosine`
+    pub(crate) distance: String,
+}
+
+/// Backend of the vector store.
+///
+/// Searching a collection, or adding points to it, fails if the collection does not exist.
+pub(crate) trait VectorStore {
+    /// Get the summary of a collection. Returns `None` if the collection does not exist.
+    async fn collection_info(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionInfo>, ServerError>;
+
+    /// List the names of the collections.
+    async fn collection_names(&self) -> Result<Vec<String>, ServerError>;
+
+    /// Create a collection of vectors of the given size.
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        distance: Distance,
+    ) -> Result<(), ServerError>;
+
+    /// Drop a collection along with all of its points.
+    async fn drop_collection(&self, collection_name: &str) -> Result<(), ServerError>;
+
+    /// Add the points to a collection. A point with the id of an existing point replaces it.
+    async fn upsert(&self, collection_name: &str, points: Vec<Point>) -> Result<(), ServerError>;
+
+    /// Search the points closest to `vector`, closest first. The vectors of the points are returned if `with_vector` is set.
+    async fn search(
+        &self,
+        collection_name: &str,
+        vector: &[f32],
+        limit: usize,
+        score_threshold: f32,
+        filter: Option<&Filter>,
+        with_vector: bool,
+    ) -> Result<Vec<SearchHit>, ServerError>;
+
+    /// Get the points of the given ids. The ids without point are left out. The vectors of the points are returned if `with_vector` is set.
+    async fn get(
+        &self,
+        collection_name: &str,
+        ids: &[PointId],
+        with_vector: bool,
+    ) -> Result<Vec<Point>, ServerError>;
+
+    /// List the points matching the filter, along with their vectors.
+    async fn scroll(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+    ) -> Result<Vec<Point>, ServerError>;
+
+    /// Delete the points matching the filter.
+    async fn delete(&self, collection_name: &str, filter: &Filter) -> Result<(), ServerError>;
+
+    /// Delete the points of the given ids.
+    async fn delete_ids(&self, collection_name: &str, ids: Vec<PointId>)
+        -> Result<(), ServerError>;
+}
+
+/// Backend selected by `--vector-store`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VectorStoreKind {
+    /// Qdrant server at `--qdrant-url`
+    #[default]
+    Qdrant,
+    /// Store embedded in the server, persisted in `--vector-store-dir`
+    Embedded,
+}
+impl fmt::Display for VectorStoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorStoreKind::Qdrant => write!(f, "qdrant"),
+            VectorStoreKind::Embedded => write!(f, "embedded"),
+        }
+    }
+}
+
+/// How the points are retrieved, selected by `--retrieval-mode` or `rag_retrieval_mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RetrievalMode {
+    /// Points closest to the query embedding
+    #[default]
+    Vector,
+    /// Points matching the query terms best, by BM25 score
+    Keyword,
+    /// Vector and keyword results fused by reciprocal rank
+    Hybrid,
+}
+impl fmt::Display for RetrievalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetrievalMode::Vector => write!(f, "vector"),
+            RetrievalMode::Keyword => write!(f, "keyword"),
+            RetrievalMode::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+/// The vector store of the server.
+pub(crate) enum Store {
+    Qdrant(QdrantStore),
+    Embedded(EmbeddedStore),
+}
+impl VectorStore for Store {
+    async fn collection_info(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionInfo>, ServerError> {
+        match self {
+            Store::Qdrant(store) => store.collection_info(collection_name).await,
+            Store::Embedded(store) => store.collection_info(collection_name).await,
+        }
+    }
+
+    async fn collection_names(&self) -> Result<Vec<String>, ServerError> {
+        match self {
+            Store::Qdrant(store) => store.collection_names().await,
+            Store::Embedded(store) => store.collection_names().await,
+        }
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        distance: Distance,
+    ) -> Result<(), ServerError> {
+        match self {
+            Store::Qdrant(store) => {
+                store
+                    .create_collection(collection_name, vector_size, distance)
+                    .await
+            }
+            Store::Embedded(store) => {
+                store
+                    .create_collection(collection_name, vector_size, distance)
+                    .await
+            }
+        }
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> Result<(), ServerError> {
+        match self {
+            Store::Qdrant(store) => store.drop_collection(collection_name).await,
+            Store::Embedded(store) => store.drop_collection(collection_name).await,
+        }
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<Point>) -> Result<(), ServerError> {
+        match self {
+            Store::Qdrant(store) => store.upsert(collection_name, points).await,
+            Store::Embedded(store) => store.upsert(collection_name, points).await,
+        }
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        vector: &[f32],
+        limit: usize,
+        score_threshold: f32,
+        filter: Option<&Filter>,
+        with_vector: bool,
+    ) -> Result<Vec<SearchHit>, ServerError> {
+        match self {
+            Store::Qdrant(store) => {
+                store
+                    .search(
+                        collection_name,
+                        vector,
+                        limit,
+                        score_threshold,
+                        filter,
+                        with_vector,
+                    )
+                    .await
+            }
+            Store::Embedded(store) => {
+                store
+                    .search(
+                        collection_name,
+                        vector,
+                        limit,
+                        score_threshold,
+                        filter,
+                        with_vector,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn get(
+        &self,
+        collection_name: &str,
+        ids: &[PointId],
+        with_vector: bool,
+    ) -> Result<Vec<Point>, ServerError> {
+        match self {
+            Store::Qdrant(store) => store.get(collection_name, ids, with_vector).await,
+            Store::Embedded(store) => store.get(collection_name, ids, with_vector).await,
+        }
+    }
+
+    async fn scroll(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+    ) -> Result<Vec<Point>, ServerError> {
+        match self {
+            Store::Qdrant(store) => store.scroll(collection_name, filter).await,
+            Store::Embedded(store) => store.scroll(collection_name, filter).await,
+        }
+    }
+
+    async fn delete(&self, collection_name: &str, filter: &Filter) -> Result<(), ServerError> {
+        match self {
+            Store::Qdrant(store) => store.delete(collection_name, filter).await,
+            Store::Embedded(store) => store.delete(collection_name, filter).await,
+        }
+    }
+
+    async fn delete_ids(
+        &self,
+        collection_name: &str,
+        ids: Vec<PointId>,
+    ) -> Result<(), ServerError> {
+        match self {
+            Store::Qdrant(store) => store.delete_ids(collection_name, ids).await,
+            Store::Embedded(store) => store.delete_ids(collection_name, ids).await,
+        }
+    }
+}
+
+fn store() -> Result<&'static Store, ServerError> {
+    VECTOR_STORE.get().ok_or(ServerError::Operation(
+        "The vector store is not set.".to_string(),
+    ))
+}
+
+/// Persist the embeddings of `chunks` in the given collection. The collection is created if it does not exist.
+///
+/// The text of each chunk is stored in the `source` field of the point payload, along with the metadata of the chunk.
+pub(crate) async fn persist_chunks(
+    collection_name: impl AsRef<str>,
+    chunks: &[DocChunk],
+    embeddings: &[EmbeddingObject],
+) -> Result<(), ServerError> {
+    if embeddings.is_empty() {
+        return Err(ServerError::Operation(
+            "No embeddings to persist.".to_string(),
+        ));
+    }
+
+    let mut points = Vec::with_capacity(embeddings.len());
+    for embedding in embeddings {
+        let chunk = match chunks.get(embedding.index as usize) {
+            Some(chunk) => chunk,
+            None => {
+                return Err(ServerError::Operation(format!(
+                    "Not found the chunk of embedding {}.",
+                    embedding.index
+                )))
+            }
+        };
+
+        points.push((
+            PointId::Uuid(uuid::Uuid::new_v4().to_string()),
+            chunk,
+            embedding.embedding.iter().map(|x| *x as f32).collect(),
+        ));
+    }
+
+    upsert_chunks(collection_name, points).await
+}
+
+/// Persist the chunks along with their vectors. A chunk with the id of an existing point replaces the point.
+pub(crate) async fn upsert_chunks(
+    collection_name: impl AsRef<str>,
+    chunks: Vec<(PointId, &DocChunk, Vec<f32>)>,
+) -> Result<(), ServerError> {
+    let collection_name = collection_name.as_ref();
+
+    let dim = match chunks.first() {
+        Some((_, _, vector)) => vector.len(),
+        None => return Ok(()),
+    };
+
+    let store = store()?;
+
+    println!("\n[+] Checking the collection ...");
+    println!("    * Collection name: {}", collection_name);
+    println!("    * Dimension: {}", dim);
+
+    match store.collection_info(collection_name).await? {
+        // a collection created by hand may not fit the embedding model
+        Some(info) if info.vector_size != 0 && info.vector_size != dim as u64 => {
+            return Err(ServerError::Operation(format!(
+                "The vectors of the collection {} have {} dimensions, but the embeddings have {}.",
+                collection_name, info.vector_size, dim
+            )))
+        }
+        // the points persisted before the keyword index are indexed first
+        Some(_) => ensure_keyword_index(store, collection_name).await?,
+        None => {
+            println!("    * Creating the collection ...");
+            store
+                .create_collection(collection_name, dim as u64, Distance::Cosine)
+                .await?;
+        }
+    }
+
+    println!("\n[+] Upserting points ...");
+
+    let points = chunks
+        .into_iter()
+        .map(|(id, chunk, vector)| {
+            // the metadata of the chunk goes along with the text of the chunk
+            let mut payload = match serde_json::to_value(&chunk.meta) {
+                Ok(Value::Object(map)) => map,
+                _ => Map::new(),
+            };
+            payload.insert("source".to_string(), Value::String(chunk.text.clone()));
+            payload.insert(
+                "chunk_hash".to_string(),
+                Value::String(registry::checksum(chunk.text.as_bytes())),
+            );
+
+            Point {
+                id,
+                vector,
+                payload,
+            }
+        })
+        .collect::<Vec<Point>>();
+
+    println!("    * Number of points: {}", points.len());
+
+    store.upsert(collection_name, points.clone()).await?;
+    keyword_index::add(collection_name, &points)
+        .or_else(|e| invalidate_keyword_index(collection_name, e))
+}
+
+/// Drop the keyword index of the collection after it failed to follow a change of the store, so that it is rebuilt from the store on next use.
+fn invalidate_keyword_index(collection_name: &str, e: ServerError) -> Result<(), ServerError> {
+    println!(
+        "    * [WARNING] Failed to update the keyword index of {}, which is rebuilt on next use. {}",
+        collection_name, e
+    );
+
+    keyword_index::drop(collection_name)
+}
+
+/// Index the points of the collection in the keyword index, if the collection has no index yet, e.g. a collection filled before the keyword index existed, or whose index failed to be updated.
+async fn ensure_keyword_index(store: &Store, collection_name: &str) -> Result<(), ServerError> {
+    if keyword_index::exists(collection_name)? {
+        return Ok(());
+    }
+
+    println!("\n[+] Building the keyword index ...");
+    println!("    * Collection name: {}", collection_name);
+
+    let points = store.scroll(collection_name, &Filter::default()).await?;
+
+    println!("    * Number of points: {}", points.len());
+
+    keyword_index::add(collection_name, &points)
+}
+
+/// A query to retrieve the points of: its text for the keyword search, and its embedding for the vector search.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Query<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) embedding: &'a [f32],
+}
+
+/// Retrieve the points relevant to the query from the given collection.
+///
+/// In the vector mode, the points are the closest to the query embedding; in the keyword mode, the ones matching the terms of the query text best. In the hybrid mode, both lists are fused by reciprocal rank, and the score of a point is its fused score. The score threshold only applies to the vector scores.
+///
+/// With `with_vectors`, the points found by the vector search carry their vectors. The points only found by the keyword search have none.
+pub(crate) async fn retrieve(
+    query: Query<'_>,
+    collection_name: impl AsRef<str>,
+    limit: usize,
+    score_threshold: Option<f32>,
+    filter: Option<&RetrieveFilter>,
+    mode: RetrievalMode,
+    with_vectors: bool,
+) -> Result<RetrieveObject, ServerError> {
+    let collection_name = collection_name.as_ref();
+    let filter = filter.and_then(RetrieveFilter::to_filter);
+    let store = store()?;
+
+    let hits = match mode {
+        RetrievalMode::Vector => {
+            store
+                .search(
+                    collection_name,
+                    query.embedding,
+                    limit,
+                    score_threshold.unwrap_or(0.0),
+                    filter.as_ref(),
+                    with_vectors,
+                )
+                .await?
+        }
+        RetrievalMode::Keyword => {
+            ensure_keyword_index(store, collection_name).await?;
+            let hits = keyword_index::search(collection_name, query.text, limit, filter.as_ref())?;
+            with_points(store, collection_name, hits, with_vectors).await?
+        }
+        RetrievalMode::Hybrid => {
+            let candidates = (limit * HYBRID_CANDIDATES)
+                .min(MAX_HYBRID_CANDIDATES)
+                .max(limit);
+            let vector_hits = store
+                .search(
+                    collection_name,
+                    query.embedding,
+                    candidates,
+                    score_threshold.unwrap_or(0.0),
+                    filter.as_ref(),
+                    with_vectors,
+                )
+                .await?;
+
+            ensure_keyword_index(store, collection_name).await?;
+            let keyword_hits =
+                keyword_index::search(collection_name, query.text, candidates, filter.as_ref())?;
+            let keyword_hits =
+                with_points(store, collection_name, keyword_hits, with_vectors).await?;
+
+            println!("    * Vector hits: {}", vector_hits.len());
+            println!("    * Keyword hits: {}", keyword_hits.len());
+
+            fuse(vec![vector_hits, keyword_hits], limit)
+        }
+    };
+
+    let points = hits
+        .into_iter()
+        .filter_map(to_scored_chunk)
+        .collect::<Vec<ScoredChunk>>();
+
+    Ok(RetrieveObject {
+        points: match points.is_empty() {
+            true => None,
+            false => Some(points),
+        },
+        limit,
+        score_threshold: score_threshold.unwrap_or(0.0),
+        rewritten_query: None,
+        expanded_queries: None,
+    })
+}
+
+/// Fill in the payloads of the keyword hits, and their vectors if `with_vector` is set, from the points of the store. The hits without point are left out.
+async fn with_points(
+    store: &Store,
+    collection_name: &str,
+    hits: Vec<SearchHit>,
+    with_vector: bool,
+) -> Result<Vec<SearchHit>, ServerError> {
+    if hits.is_empty() {
+        return Ok(hits);
+    }
+
+    let ids = hits
+        .iter()
+        .map(|hit| hit.id.clone())
+        .collect::<Vec<PointId>>();
+    let mut points = store
+        .get(collection_name, &ids, with_vector)
+        .await?
+        .into_iter()
+        .map(|point| (point.id.clone(), point))
+        .collect::<HashMap<PointId, Point>>();
+
+    Ok(hits
+        .into_iter()
+        .filter_map(|hit| {
+            let point = points.remove(&hit.id)?;
+            Some(SearchHit {
+                payload: point.payload,
+                vector: point.vector,
+                ..hit
+            })
+        })
+        .collect())
+}
+
+/// Delete the points of the chunks of the given file. If `filename` is set, only the chunks of that document of the file id are deleted.
+pub(crate) async fn delete_file(
+    collection_name: impl AsRef<str>,
+    file_id: impl AsRef<str>,
+    filename: Option<&str>,
+) -> Result<(), ServerError> {
+    let store = store()?;
+
+    // nothing to delete before the first document is persisted
+    if store
+        .collection_info(collection_name.as_ref())
+        .await?
+        .is_none()
+    {
+        return Ok(());
+    }
+
+    let filter = document_filter(file_id.as_ref(), filename);
+    store.delete(collection_name.as_ref(), &filter).await?;
+    keyword_index::remove(collection_name.as_ref(), &filter)
+        .or_else(|e| invalidate_keyword_index(collection_name.as_ref(), e))
+}
+
+/// Delete the points of the given ids.
+pub(crate) async fn delete_points(
+    collection_name: impl AsRef<str>,
+    ids: Vec<PointId>,
+) -> Result<(), ServerError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    store()?
+        .delete_ids(collection_name.as_ref(), ids.clone())
+        .await?;
+    keyword_index::remove_ids(collection_name.as_ref(), &ids)
+        .or_else(|e| invalidate_keyword_index(collection_name.as_ref(), e))
+}
+
+/// A persisted point of a document chunk.
+#[derive(Debug)]
+pub(crate) struct StoredPoint {
+    pub(crate) id: PointId,
+    /// Checksum of the text of the chunk. `None` for the points persisted before the checksums were recorded.
+    pub(crate) chunk_hash: Option<String>,
+    pub(crate) vector: Vec<f32>,
+}
+
+/// List the points of the chunks of the document `filename` of the given file.
+pub(crate) async fn document_points(
+    collection_name: impl AsRef<str>,
+    file_id: &str,
+    filename: &str,
+) -> Result<Vec<StoredPoint>, ServerError> {
+    let store = store()?;
+    let collection_name = collection_name.as_ref();
+
+    if store.collection_info(collection_name).await?.is_none() {
+        return Ok(vec![]);
+    }
+
+    let points = store
+        .scroll(collection_name, &document_filter(file_id, Some(filename)))
+        .await?;
+
+    Ok(points
+        .into_iter()
+        .map(|point| StoredPoint {
+            id: point.id,
+            chunk_hash: point
+                .payload
+                .get("chunk_hash")
+                .and_then(Value::as_str)
+                .map(|hash| hash.to_string()),
+            vector: point.vector,
+        })
+        .collect())
+}
+
+/// A document with chunks persisted in a collection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StoredDocument {
+    pub(crate) file_id: String,
+    pub(crate) filename: String,
+    /// Checksum of the document, if recorded along with its chunks
+    pub(crate) checksum: Option<String>,
+}
+
+/// List the documents with chunks persisted in the collection.
+pub(crate) async fn documents(
+    collection_name: impl AsRef<str>,
+) -> Result<Vec<StoredDocument>, ServerError> {
+    let store = store()?;
+    let collection_name = collection_name.as_ref();
+
+    if store.collection_info(collection_name).await?.is_none() {
+        return Ok(vec![]);
+    }
+
+    let points = store.scroll(collection_name, &Filter::default()).await?;
+
+    let mut documents = vec![];
+    let mut seen = HashSet::new();
+    for point in points {
+        let field = |key: &str| {
+            point
+                .payload
+                .get(key)
+                .and_then(Value::as_str)
+                .map(|value| value.to_string())
+        };
+        let document = match (field("file_id"), field("filename")) {
+            (Some(file_id), Some(filename)) => StoredDocument {
+                file_id,
+                filename,
+                checksum: field("checksum"),
+            },
+            _ => continue,
+        };
+        if seen.insert(document.clone()) {
+            documents.push(document);
+        }
+    }
+
+    Ok(documents)
+}
+
+/// Filter of the points of the chunks of the given file, or of its document `filename` only.
+fn document_filter(file_id: &str, filename: Option<&str>) -> Filter {
+    let filter = Filter::default().must("file_id", vec![file_id.to_string()]);
+    match filename {
+        Some(filename) => filter.must("filename", vec![filename.to_string()]),
+        None => filter,
+    }
+}
+
+/// Create a collection of vectors of the given size. Fails if the collection exists.
+pub(crate) async fn create_collection(
+    collection_name: impl AsRef<str>,
+    vector_size: u64,
+    distance: Distance,
+) -> Result<CollectionInfo, ServerError> {
+    let collection_name = collection_name.as_ref();
+    let store = store()?;
+
+    if store.collection_info(collection_name).await?.is_some() {
+        return Err(ServerError::Operation(format!(
+            "The collection {} already exists.",
+            collection_name
+        )));
+    }
+
+    store
+        .create_collection(collection_name, vector_size, distance)
+        .await?;
+
+    store
+        .collection_info(collection_name)
+        .await?
+        .ok_or_else(|| {
+            ServerError::Operation(format!(
+                "Not found the collection {} after creating it.",
+                collection_name
+            ))
+        })
+}
+
+/// List the collections of the vector store.
+pub(crate) async fn list_collections() -> Result<Vec<CollectionInfo>, ServerError> {
+    let store = store()?;
+
+    let mut collections = vec![];
+    for name in store.collection_names().await? {
+        // a collection dropped in the meantime is left out
+        if let Some(info) = store.collection_info(&name).await? {
+            collections.push(info);
+        }
+    }
+    collections.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(collections)
+}
+
+/// Get the summary of the given collection. Returns `None` if the collection does not exist.
+pub(crate) async fn inspect_collection(
+    collection_name: impl AsRef<str>,
+) -> Result<Option<CollectionInfo>, ServerError> {
+    store()?.collection_info(collection_name.as_ref()).await
+}
+
+/// Drop the given collection along with all of its points. Returns `false` if the collection does not exist.
+pub(crate) async fn drop_collection(collection_name: impl AsRef<str>) -> Result<bool, ServerError> {
+    let collection_name = collection_name.as_ref();
+    let store = store()?;
+
+    if store.collection_info(collection_name).await?.is_none() {
+        return Ok(false);
+    }
+
+    store.drop_collection(collection_name).await?;
+    keyword_index::drop(collection_name)?;
+
+    Ok(true)
+}
+
+/// Fuse ranked lists of hits by reciprocal rank: a hit scores `1 / (RRF_K + rank)` in each list it is in, and the best `limit` hits are kept. A hit keeps the vector of the first list it is in.
+fn fuse(lists: Vec<Vec<SearchHit>>, limit: usize) -> Vec<SearchHit> {
+    let mut fused: HashMap<PointId, SearchHit> = HashMap::new();
+    for hits in lists {
+        for (rank, hit) in hits.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            fused
+                .entry(hit.id.clone())
+                .and_modify(|fused_hit| fused_hit.score += score)
+                .or_insert(SearchHit { score, ..hit });
+        }
+    }
+
+    let mut hits = fused.into_values().collect::<Vec<SearchHit>>();
+    hits.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    hits.truncate(limit);
+
+    hits
+}
+
+fn to_scored_chunk(hit: SearchHit) -> Option<ScoredChunk> {
+    let source = hit.payload.get("source")?.as_str()?.to_string();
+    let meta: ChunkMeta = serde_json::from_value(Value::Object(hit.payload)).unwrap_or_default();
+
+    Some(ScoredChunk {
+        source,
+        score: hit.score,
+        meta,
+        id: Some(hit.id),
+        vector: hit.vector,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(id: u64, score: f32) -> SearchHit {
+        SearchHit {
+            id: PointId::Num(id),
+            score,
+            payload: Map::new(),
+            vector: vec![id as f32],
+        }
+    }
+
+    #[test]
+    fn fuse_by_reciprocal_rank() {
+        let vector_hits = vec![hit(1, 0.9), hit(2, 0.8), hit(3, 0.7)];
+        let keyword_hits = vec![hit(4, 12.0), hit(3, 9.0), hit(1, 3.0)];
+
+        let hits = fuse(vec![vector_hits, keyword_hits], 3);
+        let ids = hits.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>();
+        // the hits of both lists come first, then the best ranked of a single list
+        assert_eq!(ids, vec![PointId::Num(1), PointId::Num(3), PointId::Num(4)]);
+        assert_eq!(hits[0].score, 1.0 / (RRF_K + 1.0) + 1.0 / (RRF_K + 3.0));
+        assert_eq!(hits[2].score, 1.0 / (RRF_K + 1.0));
+    }
+
+    #[test]
+    fn fuse_keeps_the_first_vector() {
+        let mut keyword_hit = hit(1, 5.0);
+        keyword_hit.vector = vec![];
+
+        let hits = fuse(vec![vec![hit(1, 0.5)], vec![keyword_hit, hit(2, 4.0)]], 10);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].vector, vec![1.0]);
+    }
+}
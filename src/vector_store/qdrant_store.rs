@@ -0,0 +1,307 @@
+//! Vector store backed by a Qdrant server.
+
+use super::{CollectionInfo, Distance, Filter, Point, PointId, SearchHit, VectorStore};
+use crate::error::ServerError;
+use qdrant::Qdrant;
+use serde_json::{json, Value};
+
+/// Vector store of the Qdrant server at `--qdrant-url`.
+pub(crate) struct QdrantStore {
+    url: String,
+}
+impl QdrantStore {
+    pub(crate) fn new(url: impl AsRef<str>) -> Self {
+        Self {
+            url: url.as_ref().trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn client(&self) -> Qdrant {
+        Qdrant::new_with_url(self.url.clone())
+    }
+
+    /// Send a request the rest client has no api for, e.g. listing the collections or scrolling the points.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        params: Option<&Value>,
+    ) -> Result<Value, ServerError> {
+        let url = format!("{}{}", self.url, path);
+
+        let mut request = reqwest::Client::new().request(method, &url);
+        if let Some(params) = params {
+            request = request
+                .header("Content-Type", "application/json")
+                .body(params.to_string());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ServerError::Operation(format!("Failed to request {}. {}", &url, e)))?;
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ServerError::Operation(format!("Failed to request {}. {}", &url, e)))?;
+
+        serde_json::from_slice(&bytes)
+            .map_err(|e| ServerError::Operation(format!("Failed to request {}. {}", &url, e)))
+    }
+}
+impl VectorStore for QdrantStore {
+    async fn collection_info(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionInfo>, ServerError> {
+        // Qdrant answers with an error status if the collection does not exist
+        let response = self
+            .client()
+            .collection_info_api(collection_name)
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))?;
+        if response.get("status").and_then(Value::as_str) != Some("ok") {
+            return Ok(None);
+        }
+
+        let result = response.get("result").cloned().unwrap_or_default();
+        let vectors = result
+            .pointer("/config/params/vectors")
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(Some(CollectionInfo {
+            name: collection_name.to_string(),
+            status: result
+                .get("status")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            points_count: result
+                .get("points_count")
+                .and_then(Value::as_u64)
+                .unwrap_or_default(),
+            vector_size: vectors
+                .get("size")
+                .and_then(Value::as_u64)
+                .unwrap_or_default(),
+            distance: vectors
+                .get("distance")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        }))
+    }
+
+    async fn collection_names(&self) -> Result<Vec<String>, ServerError> {
+        let json = self
+            .send(reqwest::Method::GET, "/collections", None)
+            .await?;
+
+        match json
+            .pointer("/result/collections")
+            .and_then(Value::as_array)
+        {
+            Some(collections) => Ok(collections
+                .iter()
+                .filter_map(|collection| collection.get("name").and_then(Value::as_str))
+                .map(|name| name.to_string())
+                .collect()),
+            None => Err(ServerError::Operation(format!(
+                "Failed to list the collections. {}",
+                json
+            ))),
+        }
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        distance: Distance,
+    ) -> Result<(), ServerError> {
+        let params = json!({
+            "vectors": {
+                "size": vector_size,
+                "distance": distance,
+                "on_disk": true,
+            }
+        });
+
+        self.client()
+            .create_collection_api(collection_name, &params)
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> Result<(), ServerError> {
+        self.client()
+            .delete_collection_api(collection_name)
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<Point>) -> Result<(), ServerError> {
+        self.client()
+            .upsert_points_api(collection_name, &json!({ "points": points }))
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        vector: &[f32],
+        limit: usize,
+        score_threshold: f32,
+        filter: Option<&Filter>,
+        with_vector: bool,
+    ) -> Result<Vec<SearchHit>, ServerError> {
+        let mut params = json!({
+            "vector": vector,
+            "limit": limit,
+            "with_payload": true,
+            "with_vector": with_vector,
+            "score_threshold": score_threshold,
+        });
+        if let Some(filter) = filter {
+            params["filter"] = filter.to_json();
+        }
+
+        let response = self
+            .client()
+            .search_points_api(collection_name, &params)
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+        let hits = match response.get("result").and_then(Value::as_array) {
+            Some(hits) => hits,
+            None => {
+                return Err(ServerError::Operation(format!(
+                    "Failed to search points. {}",
+                    response.get("status").cloned().unwrap_or_default()
+                )))
+            }
+        };
+
+        Ok(hits
+            .iter()
+            .filter_map(|hit| {
+                Some(SearchHit {
+                    id: serde_json::from_value(hit.get("id")?.clone()).ok()?,
+                    score: hit.get("score")?.as_f64()? as f32,
+                    payload: hit.get("payload")?.as_object()?.clone(),
+                    vector: hit
+                        .get("vector")
+                        .and_then(|vector| serde_json::from_value(vector.clone()).ok())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+
+    async fn get(
+        &self,
+        collection_name: &str,
+        ids: &[PointId],
+        with_vector: bool,
+    ) -> Result<Vec<Point>, ServerError> {
+        let path = format!("/collections/{}/points", collection_name);
+        let params = json!({
+            "ids": ids,
+            "with_payload": true,
+            "with_vector": with_vector,
+        });
+
+        let json = self
+            .send(reqwest::Method::POST, &path, Some(&params))
+            .await?;
+        let result = match json.get("result").and_then(Value::as_array) {
+            Some(result) => result,
+            None => {
+                return Err(ServerError::Operation(format!(
+                    "Failed to get the points. {}",
+                    json
+                )))
+            }
+        };
+
+        Ok(result
+            .iter()
+            .filter_map(|point| serde_json::from_value::<Point>(point.clone()).ok())
+            .collect())
+    }
+
+    async fn scroll(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+    ) -> Result<Vec<Point>, ServerError> {
+        let path = format!("/collections/{}/points/scroll", collection_name);
+
+        let mut points = vec![];
+        let mut offset = Value::Null;
+        loop {
+            let mut params = json!({
+                "limit": 256,
+                "with_payload": true,
+                "with_vector": true,
+            });
+            // an empty filter scrolls all the points
+            if !filter.is_empty() {
+                params["filter"] = filter.to_json();
+            }
+            if !offset.is_null() {
+                params["offset"] = offset;
+            }
+
+            let json = self
+                .send(reqwest::Method::POST, &path, Some(&params))
+                .await?;
+            let result = match json.get("result") {
+                Some(result) => result,
+                None => {
+                    return Err(ServerError::Operation(format!(
+                        "Failed to scroll the points. {}",
+                        json
+                    )))
+                }
+            };
+
+            for point in result
+                .get("points")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                if let Ok(point) = serde_json::from_value::<Point>(point.clone()) {
+                    points.push(point);
+                }
+            }
+
+            offset = result.get("next_page_offset").cloned().unwrap_or_default();
+            if offset.is_null() {
+                break;
+            }
+        }
+
+        Ok(points)
+    }
+
+    async fn delete(&self, collection_name: &str, filter: &Filter) -> Result<(), ServerError> {
+        self.client()
+            .delete_points_api(collection_name, &json!({ "filter": filter.to_json() }))
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))
+    }
+
+    async fn delete_ids(
+        &self,
+        collection_name: &str,
+        ids: Vec<PointId>,
+    ) -> Result<(), ServerError> {
+        self.client()
+            .delete_points_api(collection_name, &json!({ "points": ids }))
+            .await
+            .map_err(|e| ServerError::Operation(e.to_string()))
+    }
+}
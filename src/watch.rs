@@ -0,0 +1,221 @@
+//! Keep the Qdrant collection in sync with the documents of a local directory.
+//!
+//! WASI has no file system notifications, so the directory is scanned at a fixed interval. New and modified documents are re-embedded, and the points of deleted documents are removed.
+//!
+//! The points of the watched documents record the checksum of their document, so that on startup the documents left unchanged since the last run are not embedded again, and the points of those deleted meanwhile are removed.
+
+use crate::{
+    backend::ggml,
+    error::ServerError,
+    ingest, registry,
+    utils::{log, walk_dir},
+    vector_store, SERVER_INFO,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Prefix of the ids of the watched documents
+const FILE_ID_PREFIX: &str = "watch_";
+
+/// Version of a document, compared between two scans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// Index the documents in `dir`, then rescan it every `interval`. Never returns.
+pub(crate) async fn watch(dir: PathBuf, interval: Duration, chunk_capacity: usize) {
+    // the ids of the documents are derived from their absolute paths
+    let dir = fs::canonicalize(&dir).unwrap_or(dir);
+
+    let mut indexed = match seed(&dir).await {
+        Ok(indexed) => indexed,
+        Err(e) => {
+            log(format!(
+                "    * [ERROR] Failed to check the indexed documents, indexing all of them. {}",
+                e
+            ));
+            HashMap::new()
+        }
+    };
+
+    loop {
+        let documents = scan(&dir);
+
+        // documents deleted since the last scan
+        let deleted = indexed
+            .keys()
+            .filter(|filename| !documents.contains_key(*filename))
+            .cloned()
+            .collect::<Vec<String>>();
+        for filename in deleted {
+            log(format!("\n[+] Watched document deleted: {}", &filename));
+
+            match remove(&dir, &filename).await {
+                Ok(_) => {
+                    indexed.remove(&filename);
+                }
+                Err(e) => log(format!("    * [ERROR] {}", e)),
+            }
+        }
+
+        // documents created or modified since the last scan
+        for (filename, fingerprint) in documents {
+            if indexed.get(&filename) == Some(&fingerprint) {
+                continue;
+            }
+
+            log(format!("\n[+] Indexing watched document: {}", &filename));
+
+            // a document that fails to index, e.g. one saved half-written, is retried by the next scan
+            match index(&dir, &filename, chunk_capacity).await {
+                Ok(chunks) => {
+                    log(format!("    * Number of chunks: {}", chunks));
+                    indexed.insert(filename, fingerprint);
+                }
+                Err(e) => log(format!("    * [ERROR] {}", e)),
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Find the documents of `dir` whose points are up to date in the collection, and remove the points of the watched documents which no longer exist.
+async fn seed(dir: &Path) -> Result<HashMap<String, Fingerprint>, ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+    let collection_name = &server_info.qdrant_config.collection_name;
+
+    log("\n[+] Checking the indexed watched documents ...".to_string());
+
+    let documents = scan(dir);
+    let ids = documents
+        .keys()
+        .map(|filename| (file_id(dir, filename), filename))
+        .collect::<HashMap<String, &String>>();
+
+    let mut indexed = HashMap::new();
+    let mut outdated = HashSet::new();
+    let mut removed = HashSet::new();
+    for stored in vector_store::documents(collection_name)
+        .await?
+        .into_iter()
+        .filter(|stored| stored.file_id.starts_with(FILE_ID_PREFIX))
+    {
+        match ids.get(&stored.file_id) {
+            Some(filename) => {
+                // a modified document is indexed again by the first scan, and so is a document with the points of a previous version left, e.g. by a stop in the middle of its indexing
+                let checksum = fs::read(dir.join(filename))
+                    .ok()
+                    .map(|bytes| registry::checksum(&bytes));
+                if checksum.is_some() && checksum == stored.checksum {
+                    indexed.insert(filename.to_string(), documents[*filename].clone());
+                } else {
+                    outdated.insert(filename.to_string());
+                }
+            }
+            None => {
+                if !removed.insert(stored.file_id.clone()) {
+                    continue;
+                }
+                log(format!(
+                    "    * Watched document deleted: {}",
+                    &stored.filename
+                ));
+                vector_store::delete_file(collection_name, &stored.file_id, None).await?;
+            }
+        }
+    }
+
+    indexed.retain(|filename, _| !outdated.contains(filename));
+
+    log(format!("    * Up-to-date documents: {}", indexed.len()));
+
+    Ok(indexed)
+}
+
+/// Id of a watched document. The id is derived from the path, so it stays the same across restarts.
+fn file_id(dir: &Path, filename: &str) -> String {
+    let path = dir.join(filename);
+
+    format!(
+        "{}{}",
+        FILE_ID_PREFIX,
+        uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_URL,
+            path.to_string_lossy().as_bytes()
+        )
+    )
+}
+
+async fn index(dir: &Path, filename: &str, chunk_capacity: usize) -> Result<usize, ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+    let collection_name = &server_info.qdrant_config.collection_name;
+    let id = file_id(dir, filename);
+
+    // the points of the previous version are dropped once the new version is indexed, so that they are kept if it fails to
+    let stale = vector_store::document_points(collection_name, &id, filename)
+        .await?
+        .into_iter()
+        .map(|point| point.id)
+        .collect::<Vec<vector_store::PointId>>();
+
+    // the modification time stands in for the upload time
+    let file_path = dir.join(filename);
+    let modified = fs::metadata(&file_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let checksum = fs::read(&file_path)
+        .map(|bytes| registry::checksum(&bytes))
+        .map_err(|e| ServerError::Operation(format!("Failed to read {}. {}", filename, e)))?;
+    let document = ingest::ChunkMeta {
+        checksum: Some(checksum),
+        ..ggml::document_meta(&id, filename, modified, &BTreeMap::new())
+    };
+    let chunks = ggml::index_file(file_path, &document, collection_name, chunk_capacity).await?;
+
+    vector_store::delete_points(collection_name, stale).await?;
+
+    Ok(chunks)
+}
+
+async fn remove(dir: &Path, filename: &str) -> Result<(), ServerError> {
+    let server_info = SERVER_INFO.get().ok_or(ServerError::Operation(
+        "The server info is not set.".to_string(),
+    ))?;
+
+    vector_store::delete_file(
+        &server_info.qdrant_config.collection_name,
+        file_id(dir, filename),
+        None,
+    )
+    .await
+}
+
+/// Find the supported documents in `dir` and its subdirectories. Hidden files and directories are skipped.
+fn scan(dir: &Path) -> HashMap<String, Fingerprint> {
+    walk_dir(dir)
+        .into_iter()
+        .filter(|(filename, _)| ingest::is_supported(filename))
+        .map(|(filename, metadata)| {
+            let fingerprint = Fingerprint {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            };
+            (filename, fingerprint)
+        })
+        .collect()
+}